@@ -0,0 +1,193 @@
+use crate::completions::Shell;
+use crate::packages::Package;
+use log::debug;
+use std::error::Error;
+
+/// Returns shell code defining a function for each installed package and binary shim. The
+/// code also wraps `hbox` to support `hbox deactivate` and re-evaluates itself whenever the
+/// current directory changes, so packages added or pinned in a project are picked up.
+pub fn activate(shell: Shell) -> Result<String, Box<dyn Error>> {
+    let mut commands = Vec::new();
+    for package in Package::load_all()? {
+        for shim in package.shim_names() {
+            if !is_valid_function_name(&shim) {
+                debug!("Skipping function for '{}', it's not a valid name", shim);
+                continue;
+            }
+            let command = package.shim_command(&shim);
+            commands.push((shim, command));
+        }
+    }
+    let names = commands
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut script = String::new();
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            let split = if shell == Shell::Zsh { "=" } else { "" };
+            script.push_str(&format!(
+                "for f in ${{{}_HBOX_FUNCTIONS}}; do unset -f \"$f\"; done\n",
+                split
+            ));
+            for (name, command) in &commands {
+                script.push_str(&format!(
+                    "{}() {{ command hbox run {} \"$@\"; }}\n",
+                    name, command
+                ));
+            }
+            script.push_str(&format!("_HBOX_FUNCTIONS=\"{}\"\n", names));
+            script.push_str(&format!(
+                r#"hbox() {{
+    if [ "$1" = "deactivate" ]; then
+        eval "$(command hbox deactivate {shell})"
+    else
+        command hbox "$@"
+    fi
+}}
+"#,
+                shell = shell_name(shell)
+            ));
+            if shell == Shell::Zsh {
+                script.push_str(
+                    r#"_hbox_hook() { eval "$(command hbox activate zsh)"; }
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _hbox_hook
+"#,
+                );
+            } else {
+                script.push_str(
+                    r#"_hbox_hook() {
+    if [ "$PWD" != "$_HBOX_PWD" ]; then
+        _HBOX_PWD="$PWD"
+        eval "$(command hbox activate bash)"
+    fi
+}
+case ";$PROMPT_COMMAND;" in
+    *";_hbox_hook;"*) ;;
+    *) PROMPT_COMMAND="_hbox_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}" ;;
+esac
+_HBOX_PWD="$PWD"
+"#,
+                );
+            }
+        }
+        Shell::Fish => {
+            script.push_str("for f in $_hbox_functions; functions -e $f; end\n");
+            for (name, command) in &commands {
+                script.push_str(&format!(
+                    "function {}; command hbox run {} $argv; end\n",
+                    name, command
+                ));
+            }
+            script.push_str(&format!("set -g _hbox_functions {}\n", names));
+            script.push_str(
+                r#"function hbox
+    if test "$argv[1]" = deactivate
+        command hbox deactivate fish | source
+    else
+        command hbox $argv
+    end
+end
+function _hbox_hook --on-variable PWD
+    command hbox activate fish | source
+end
+"#,
+            );
+        }
+        Shell::Powershell => {
+            script.push_str(
+                r#"foreach ($f in $global:HboxFunctions) { Remove-Item -Path "Function:\global:$f" -ErrorAction SilentlyContinue }
+$global:HboxExe = (Get-Command hbox -CommandType Application | Select-Object -First 1).Source
+"#,
+            );
+            for (name, command) in &commands {
+                script.push_str(&format!(
+                    "function global:{} {{ & $global:HboxExe run {} @args }}\n",
+                    name, command
+                ));
+            }
+            script.push_str(&format!(
+                "$global:HboxFunctions = @({})\n",
+                commands
+                    .iter()
+                    .map(|(name, _)| format!("'{}'", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            script.push_str(
+                r#"function global:hbox {
+    if ($args[0] -eq 'deactivate') {
+        & $global:HboxExe deactivate powershell | Out-String | Invoke-Expression
+    } else {
+        & $global:HboxExe @args
+    }
+}
+if (-not $global:HboxOriginalPrompt) { $global:HboxOriginalPrompt = $function:prompt }
+$global:HboxPwd = $PWD.Path
+function global:prompt {
+    if ($PWD.Path -ne $global:HboxPwd) {
+        $global:HboxPwd = $PWD.Path
+        & $global:HboxExe activate powershell | Out-String | Invoke-Expression
+    }
+    & $global:HboxOriginalPrompt
+}
+"#,
+            );
+        }
+    }
+    Ok(script)
+}
+
+/// Returns shell code removing everything defined by `activate`.
+pub fn deactivate(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => {
+            r#"for f in $_HBOX_FUNCTIONS; do unset -f "$f"; done
+unset -f hbox _hbox_hook
+unset _HBOX_FUNCTIONS _HBOX_PWD
+PROMPT_COMMAND="${PROMPT_COMMAND//_hbox_hook;/}"
+PROMPT_COMMAND="${PROMPT_COMMAND//_hbox_hook/}"
+"#
+        }
+        Shell::Zsh => {
+            r#"for f in ${=_HBOX_FUNCTIONS}; do unset -f "$f"; done
+add-zsh-hook -d chpwd _hbox_hook
+unset -f hbox _hbox_hook
+unset _HBOX_FUNCTIONS
+"#
+        }
+        Shell::Fish => {
+            r#"for f in $_hbox_functions; functions -e $f; end
+functions -e hbox _hbox_hook
+set -e _hbox_functions
+"#
+        }
+        Shell::Powershell => {
+            r#"foreach ($f in $global:HboxFunctions) { Remove-Item -Path "Function:\global:$f" -ErrorAction SilentlyContinue }
+Remove-Item -Path "Function:\global:hbox" -ErrorAction SilentlyContinue
+if ($global:HboxOriginalPrompt) { $function:global:prompt = $global:HboxOriginalPrompt }
+Remove-Variable -Scope Global -Name HboxFunctions, HboxExe, HboxPwd, HboxOriginalPrompt -ErrorAction SilentlyContinue
+"#
+        }
+    }
+}
+
+fn shell_name(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => "bash",
+        Shell::Zsh => "zsh",
+        Shell::Fish => "fish",
+        Shell::Powershell => "powershell",
+    }
+}
+
+fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+}
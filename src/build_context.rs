@@ -0,0 +1,380 @@
+use crate::configs::app::AppConfig;
+use crate::dry_run::is_dry_run;
+use crate::serialization::{file_checksum, parse_json, write_atomically};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Files modified more recently than this aren't cached, as they could change again without
+/// changing their size or modification time.
+const RECENT_CHANGE: Duration = Duration::from_secs(2);
+
+/// Checksums of the files of a build context, keyed by their relative path, so `run` only
+/// hashes the files whose size or modification time changed since the last check.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+struct ChecksumCache {
+    files: BTreeMap<String, CachedChecksum>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct CachedChecksum {
+    size: u64,
+    modified: u128,
+    sha256: String,
+}
+
+/// Hashes the paths and contents of the files in a build context, skipping the ones excluded
+/// by its `.dockerignore` file, as the engine does when sending the context.
+pub fn hash_build_context(dir: &Path, hasher: &mut Sha256) -> Result<(), Box<dyn Error>> {
+    hash_files(dir, &cache_file(dir), hasher)
+}
+
+fn hash_files(dir: &Path, cache_file: &Path, hasher: &mut Sha256) -> Result<(), Box<dyn Error>> {
+    let ignore =
+        DockerIgnore::parse(&fs::read_to_string(dir.join(".dockerignore")).unwrap_or_default());
+    let cache: ChecksumCache = parse_json(cache_file).unwrap_or_default();
+    let mut updated = ChecksumCache::default();
+
+    for (name, metadata) in context_files(dir, &ignore)? {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        let path = dir.join(&name);
+        if metadata.is_symlink() {
+            hasher.update(fs::read_link(&path)?.to_string_lossy().as_bytes());
+        } else {
+            let checksum = cached_checksum(&cache, &name, &path, &metadata)?;
+            hasher.update(checksum.sha256.as_bytes());
+            if !is_recent(&metadata) {
+                updated.files.insert(name, checksum);
+            }
+        }
+        hasher.update([0]);
+    }
+
+    if updated != cache && !is_dry_run() {
+        debug!("Saving the checksums of build context {:?}", dir);
+        if let Some(parent) = cache_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(cache_file, &serde_json::to_vec(&updated)?)?;
+    }
+    Ok(())
+}
+
+/// Files of a build context that aren't ignored, with their relative paths, sorted.
+fn context_files(
+    dir: &Path,
+    ignore: &DockerIgnore,
+) -> Result<Vec<(String, Metadata)>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            let name = path.to_string_lossy().replace('\\', "/");
+            let ignored = ignore.is_ignored(&name);
+            let metadata = fs::symlink_metadata(entry.path())?;
+            if metadata.is_dir() {
+                // Exceptions can include files of an ignored directory, so it's only skipped
+                // when there are none.
+                if !ignored || ignore.has_exceptions() {
+                    pending.push(path);
+                }
+            } else if !ignored {
+                files.push((name, metadata));
+            }
+        }
+    }
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(files)
+}
+
+fn cached_checksum(
+    cache: &ChecksumCache,
+    name: &str,
+    path: &Path,
+    metadata: &Metadata,
+) -> Result<CachedChecksum, Box<dyn Error>> {
+    let size = metadata.len();
+    let modified = modified_nanos(metadata);
+    if let Some(cached) = cache.files.get(name) {
+        if cached.size == size && cached.modified == modified {
+            return Ok(cached.clone());
+        }
+    }
+    Ok(CachedChecksum {
+        size,
+        modified,
+        sha256: file_checksum(path)?,
+    })
+}
+
+fn modified_nanos(metadata: &Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos())
+}
+
+fn is_recent(metadata: &Metadata) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_none_or(|age| age < RECENT_CHANGE)
+}
+
+/// Cache file of a build context, named after a hash of its path.
+fn cache_file(dir: &Path) -> PathBuf {
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let digest = format!("{:x}", Sha256::digest(dir.to_string_lossy().as_bytes()));
+    AppConfig::load()
+        .cache_path()
+        .join("build-contexts")
+        .join(format!("{}.json", &digest[..16]))
+}
+
+/// Patterns of a `.dockerignore` file, matched like the engine does: `*` and `?` don't match
+/// `/`, `**` matches any number of directories, a pattern matching a directory also matches
+/// everything in it, and the last matching pattern wins, so `!` exceptions can include files
+/// again.
+pub struct DockerIgnore {
+    patterns: Vec<IgnorePattern>,
+}
+
+struct IgnorePattern {
+    tokens: Vec<Token>,
+    exception: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`, any character but `/`.
+    Any,
+    /// `*`, any number of characters but `/`.
+    Star,
+    /// `**` (or `**/`) before more of the pattern, any number of directories, even none.
+    Dirs,
+    /// `**` at the end of the pattern, anything.
+    Rest,
+    /// `[...]`, a character in (or not in, when negated) a set of ranges.
+    Class(Vec<(char, char)>, bool),
+}
+
+impl DockerIgnore {
+    pub fn parse(contents: &str) -> Self {
+        let patterns = contents
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (exception, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern.trim()),
+                    None => (false, line),
+                };
+                let pattern = clean_path(pattern);
+                (!pattern.is_empty()).then(|| IgnorePattern {
+                    tokens: tokenize(&pattern),
+                    exception,
+                })
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    pub fn has_exceptions(&self) -> bool {
+        self.patterns.iter().any(|pattern| pattern.exception)
+    }
+
+    /// Indicates if a path, relative to the build context and separated by `/`, is ignored.
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let parents: Vec<&str> = path
+            .match_indices('/')
+            .map(|(index, _)| &path[..index])
+            .collect();
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            // Only patterns that can change the outcome are evaluated.
+            if pattern.exception != ignored {
+                continue;
+            }
+            let chars: Vec<char> = path.chars().collect();
+            let matched = matches(&pattern.tokens, &chars)
+                || parents
+                    .iter()
+                    .any(|parent| matches(&pattern.tokens, &parent.chars().collect::<Vec<_>>()));
+            if matched {
+                ignored = !pattern.exception;
+            }
+        }
+        ignored
+    }
+}
+
+/// Normalizes a pattern like Go's `filepath.Clean`, relative to the build context.
+fn clean_path(pattern: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in pattern.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                }
+                tokens.push(if chars.peek().is_none() {
+                    Token::Rest
+                } else {
+                    Token::Dirs
+                });
+            }
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '\\' => tokens.push(Token::Char(chars.next().unwrap_or('\\'))),
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut ranges = Vec::new();
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        break;
+                    }
+                    let start = if c == '\\' {
+                        chars.next().unwrap_or('\\')
+                    } else {
+                        c
+                    };
+                    let end = if chars.peek() == Some(&'-') {
+                        chars.next();
+                        chars.next().unwrap_or(start)
+                    } else {
+                        start
+                    };
+                    ranges.push((start, end));
+                }
+                tokens.push(Token::Class(ranges, negated));
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+fn matches(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Char(c) => path.first() == Some(c) && matches(rest, &path[1..]),
+        Token::Any => path.first().is_some_and(|c| *c != '/') && matches(rest, &path[1..]),
+        Token::Class(ranges, negated) => {
+            path.first().is_some_and(|c| {
+                ranges.iter().any(|(start, end)| (start..=end).contains(&c)) != *negated
+            }) && matches(rest, &path[1..])
+        }
+        Token::Star => {
+            let end = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            (0..=end).any(|i| matches(rest, &path[i..]))
+        }
+        Token::Dirs => {
+            matches(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == '/')
+                    .any(|(i, _)| matches(rest, &path[i + 1..]))
+        }
+        Token::Rest => true,
+    }
+}
+
+#[test]
+fn verify_dockerignore_patterns() {
+    let ignore = DockerIgnore::parse(
+        "# comment\n*.md\n!README.md\n./target/\n**/*.log\ndocs/*/draft?.txt\n/tmp\n[ab]*.bin\n",
+    );
+    assert!(ignore.is_ignored("CHANGES.md"));
+    assert!(!ignore.is_ignored("README.md"));
+    assert!(!ignore.is_ignored("docs/guide.md"));
+    assert!(ignore.is_ignored("target"));
+    assert!(ignore.is_ignored("target/release/hbox"));
+    assert!(ignore.is_ignored("app.log"));
+    assert!(ignore.is_ignored("logs/today/app.log"));
+    assert!(ignore.is_ignored("docs/v1/draft1.txt"));
+    assert!(!ignore.is_ignored("docs/v1/nested/draft1.txt"));
+    assert!(!ignore.is_ignored("docs/v1/draft10.txt"));
+    assert!(ignore.is_ignored("tmp/cache"));
+    assert!(ignore.is_ignored("a1.bin"));
+    assert!(!ignore.is_ignored("c1.bin"));
+    assert!(!ignore.is_ignored("src/main.rs"));
+
+    let ignore = DockerIgnore::parse("vendor\n!vendor/keep/**\n");
+    assert!(ignore.has_exceptions());
+    assert!(ignore.is_ignored("vendor/lib.rs"));
+    assert!(!ignore.is_ignored("vendor/keep/lib.rs"));
+    assert!(!ignore.is_ignored("vendor/keep/nested/lib.rs"));
+}
+
+#[test]
+fn verify_build_context_hashing() {
+    let context = crate::serialization::TempDir::create("context").unwrap();
+    let cache_file = context.path.join("cache.json");
+    fs::create_dir(context.path.join("app")).unwrap();
+    let dir = context.path.join("app");
+    fs::write(dir.join(".dockerignore"), "*.log\n").unwrap();
+    fs::write(dir.join("main.sh"), "echo hi").unwrap();
+    let old = SystemTime::now() - Duration::from_secs(60);
+    fs::File::options()
+        .write(true)
+        .open(dir.join("main.sh"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let hash = || {
+        let mut hasher = Sha256::new();
+        hash_files(&dir, &cache_file, &mut hasher).unwrap();
+        format!("{:x}", hasher.finalize())
+    };
+    let original = hash();
+    // The .dockerignore file was just written, so it's hashed but not cached yet.
+    let cache: ChecksumCache = parse_json(&cache_file).unwrap();
+    assert_eq!(cache.files.keys().collect::<Vec<_>>(), vec!["main.sh"]);
+
+    fs::write(dir.join("debug.log"), "ignored").unwrap();
+    assert_eq!(hash(), original);
+
+    // A file with the same size and modification time isn't hashed again.
+    let main = fs::File::options()
+        .write(true)
+        .open(dir.join("main.sh"))
+        .unwrap();
+    std::io::Write::write_all(&mut &main, b"echo yo").unwrap();
+    main.set_modified(old).unwrap();
+    assert_eq!(hash(), original);
+
+    fs::write(dir.join("main.sh"), "echo bye").unwrap();
+    assert_ne!(hash(), original);
+}
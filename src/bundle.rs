@@ -0,0 +1,438 @@
+use crate::commands::{install_package, prepare_package};
+use crate::configs::app::AppConfig;
+use crate::configs::context::Context;
+use crate::configs::index::{IndexConfig, Package as PackageIndex};
+use crate::configs::version::VersionConfig;
+use crate::dry_run::{is_dry_run, plan};
+use crate::packages::Package;
+use crate::report;
+use crate::runner::{image_digest, image_reference, load_image, save_image};
+use crate::serialization::{file_name_part, save_json};
+use chrono::Local;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use tar::{Archive, Builder, Header};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const BUNDLE_FORMAT: u32 = 1;
+
+/// Installed packages with the versions to include in a bundle.
+type Selection = Vec<(Package, Vec<String>)>;
+
+/// Describes the content of a bundle. It's the last file of the archive, after the images
+/// saved by the container engine, so their checksums are computed while they're written.
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    format: u32,
+    created_at: String,
+    packages: Vec<BundledPackage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BundledPackage {
+    name: String,
+    current: String,
+    index: PackageIndex,
+    images: Vec<BundledImage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BundledImage {
+    version: String,
+    image: String,
+    file: String,
+    sha256: String,
+}
+
+/// Writer computing the SHA-256 digest of what's written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.hasher.update(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Saves the images, versions and definitions of installed packages into a single archive,
+/// so they can be installed on machines without registry access. Images are streamed from
+/// the engine into the archive, without being saved to separate files first.
+pub fn create_bundle(specs: &[String], file: &Path) -> Result<(), Box<dyn Error>> {
+    let selection = select_versions(specs)?;
+    let mut manifest = Manifest {
+        format: BUNDLE_FORMAT,
+        created_at: Local::now().to_rfc3339(),
+        packages: Vec::new(),
+    };
+    for (package, versions) in selection {
+        let mut images = Vec::new();
+        for version in versions {
+            let mut versioned = package.clone();
+            versioned.versions.current = version.clone();
+            let image = image_reference(&versioned, &Context::from(&versioned))?;
+            let entry = format!(
+                "images/{}-{}.tar",
+                file_name_part(&package.name),
+                file_name_part(&version)
+            );
+            images.push(BundledImage {
+                version,
+                image,
+                file: entry,
+                sha256: String::new(),
+            });
+        }
+
+        let current = if images.iter().any(|i| i.version == package.versions.current) {
+            package.versions.current.clone()
+        } else {
+            images[0].version.clone()
+        };
+        manifest.packages.push(BundledPackage {
+            name: package.name.clone(),
+            current,
+            index: package.index.clone(),
+            images,
+        });
+    }
+
+    if is_dry_run() {
+        for image in manifest.packages.iter().flat_map(|p| &p.images) {
+            save_image(&image.image, &mut io::sink())?;
+        }
+        plan(format!("write {}", file.display()));
+        return Ok(());
+    }
+    write_bundle(&mut manifest, file)?;
+    report!(
+        "Created bundle {:?} with {} image(s) of {} package(s).",
+        file,
+        manifest
+            .packages
+            .iter()
+            .map(|p| p.images.len())
+            .sum::<usize>(),
+        manifest.packages.len()
+    );
+    Ok(())
+}
+
+/// Loads the images of a bundle and registers its packages. The checksum of every image is
+/// verified first, reading the images straight from the archive, and they're streamed to the
+/// engine from it afterwards.
+pub fn install_bundle(file: &Path) -> Result<(), Box<dyn Error>> {
+    let manifest = verify_bundle(&mut open_bundle(file)?)?;
+    debug!("Verified the checksums of bundle {:?}", file);
+
+    let mut new_images = HashSet::new();
+    for entry in open_bundle(file)?.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let Some(image) = manifest
+            .packages
+            .iter()
+            .flat_map(|p| &p.images)
+            .find(|image| image.file == path)
+        else {
+            continue;
+        };
+        if image_digest(&image.image).is_none() {
+            new_images.insert(image.image.clone());
+        }
+        report!("Loading image '{}'...", image.image);
+        load_image(&mut entry)?;
+    }
+
+    let mut installed = 0;
+    for package in &manifest.packages {
+        installed += install_bundled_package(package, &new_images)?;
+    }
+    report!(
+        "Installed {} package version(s) from bundle {:?}.",
+        installed,
+        file
+    );
+    Ok(())
+}
+
+fn open_bundle(file: &Path) -> Result<Archive<BufReader<File>>, Box<dyn Error>> {
+    let bundle =
+        File::open(file).map_err(|e| format!("Could not open bundle {:?}: {}", file, e))?;
+    Ok(Archive::new(BufReader::new(bundle)))
+}
+
+/// Reads the manifest of a bundle and verifies the checksum of every image it lists, hashing
+/// the images as they're read from the archive.
+fn verify_bundle(archive: &mut Archive<impl Read>) -> Result<Manifest, Box<dyn Error>> {
+    let mut manifest = None;
+    let mut checksums = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        if path == MANIFEST_FILE {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            manifest = Some(
+                serde_json::from_slice::<Manifest>(&contents)
+                    .map_err(|e| format!("Invalid bundle {}: {}", MANIFEST_FILE, e))?,
+            );
+        } else {
+            let mut hasher = Sha256::new();
+            io::copy(&mut entry, &mut hasher)?;
+            checksums.insert(path, format!("{:x}", hasher.finalize()));
+        }
+    }
+
+    let manifest =
+        manifest.ok_or_else(|| format!("Invalid bundle: {} is missing.", MANIFEST_FILE))?;
+    if manifest.format != BUNDLE_FORMAT {
+        return Err(format!("Unsupported bundle format {}.", manifest.format).into());
+    }
+    for image in manifest.packages.iter().flat_map(|p| &p.images) {
+        match checksums.get(&image.file) {
+            None => return Err(format!("Invalid bundle: {} is missing.", image.file).into()),
+            Some(checksum) if *checksum != image.sha256 => {
+                return Err(format!("Checksum mismatch for image '{}'.", image.image).into())
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(manifest)
+}
+
+/// Returns the installed packages and versions matching `NAME` (all versions) or
+/// `NAME@VERSION` specs.
+fn select_versions(specs: &[String]) -> Result<Selection, Box<dyn Error>> {
+    let mut selection: Selection = Vec::new();
+    for spec in specs {
+        let (name, version) = match spec.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (spec.as_str(), None),
+        };
+        let package =
+            Package::load(name)?.ok_or_else(|| format!("Package '{}' is not installed.", name))?;
+        let versions = match version {
+            Some(version) if package.versions.versions.iter().any(|v| v == version) => {
+                vec![version.to_string()]
+            }
+            Some(version) => {
+                return Err(format!("'{}' version {} is not installed.", name, version).into())
+            }
+            None => package.versions.versions.clone(),
+        };
+
+        match selection.iter_mut().find(|(p, _)| p.name == package.name) {
+            Some((_, selected)) => {
+                for version in versions {
+                    if !selected.contains(&version) {
+                        selected.push(version);
+                    }
+                }
+            }
+            None => selection.push((package, versions)),
+        }
+    }
+    Ok(selection)
+}
+
+/// Writes the bundle to a temporary file, renamed to `file` once complete. The checksums of
+/// the images are recorded in the manifest as they're streamed into the archive.
+fn write_bundle(manifest: &mut Manifest, file: &Path) -> Result<(), Box<dyn Error>> {
+    let file_name = file
+        .file_name()
+        .ok_or_else(|| format!("Invalid bundle path {:?}", file))?;
+    let temp_file = file.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let mtime = Local::now().timestamp().max(0) as u64;
+        let mut builder = Builder::new(BufWriter::new(File::create(&temp_file)?));
+        for image in manifest.packages.iter_mut().flat_map(|p| &mut p.images) {
+            report!("Saving image '{}'...", image.image);
+            let mut header = entry_header(mtime);
+            let mut entry = builder.append_writer(&mut header, &image.file)?;
+            let mut writer = HashingWriter {
+                inner: &mut entry,
+                hasher: Sha256::new(),
+            };
+            save_image(&image.image, &mut writer)?;
+            image.sha256 = format!("{:x}", writer.hasher.finalize());
+            entry.finish()?;
+        }
+
+        let contents = serde_json::to_vec_pretty(manifest)?;
+        let mut header = entry_header(mtime);
+        header.set_size(contents.len() as u64);
+        builder.append_data(&mut header, MANIFEST_FILE, contents.as_slice())?;
+        builder.into_inner()?.into_inner()?.sync_all()?;
+        fs::rename(&temp_file, file)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_file);
+    }
+    result
+}
+
+fn entry_header(mtime: u64) -> Header {
+    let mut header = Header::new_ustar();
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header
+}
+
+/// Registers the versions of a bundled package, whose images are already loaded. The bundled
+/// definition is removed again if it was saved and no version of the package ends up installed.
+fn install_bundled_package(
+    bundled: &BundledPackage,
+    new_images: &HashSet<String>,
+) -> Result<usize, Box<dyn Error>> {
+    let override_file = register_definition(&bundled.name, &bundled.index)?;
+    let result = install_bundled_versions(bundled, new_images);
+    if let (Err(_), Some(override_file)) = (&result, override_file) {
+        if VersionConfig::load(bundled.name.clone())?.is_none() {
+            debug!("Removing the bundled definition of '{}'", bundled.name);
+            fs::remove_file(override_file)?;
+        }
+    }
+    result
+}
+
+fn install_bundled_versions(
+    bundled: &BundledPackage,
+    new_images: &HashSet<String>,
+) -> Result<usize, Box<dyn Error>> {
+    let previous = VersionConfig::load(bundled.name.clone())?;
+    let mut installed = 0;
+    for image in &bundled.images {
+        if previous
+            .as_ref()
+            .is_some_and(|package| package.versions.contains(&image.version))
+        {
+            report!(
+                "'{}' version '{}' is already installed.",
+                bundled.name,
+                image.version
+            );
+            continue;
+        }
+        let set_default = previous.is_none() && image.version == bundled.current;
+        let package = prepare_package(&bundled.name, &image.version, set_default)?;
+        install_package(
+            &bundled.name,
+            package,
+            new_images.get(&image.image).cloned(),
+        )?;
+        report!("Added '{}' version '{}'.", bundled.name, image.version);
+        installed += 1;
+    }
+    Ok(installed)
+}
+
+/// Saves the bundled definition of a package as an override, unless the index or overrides
+/// already have the same definition. An existing override is never replaced. Returns the
+/// override file if it was saved.
+fn register_definition(
+    name: &str,
+    index: &PackageIndex,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let current = IndexConfig::load(name.to_string())?;
+    if serde_json::to_value(&current)? == serde_json::to_value(index)? {
+        return Ok(None);
+    }
+
+    let config = AppConfig::load();
+    let override_file = config.overrides_path().join(format!("{}.json", name));
+    if override_file.exists() {
+        warn!(
+            "Keeping override {:?}, which differs from the definition of '{}' in the bundle",
+            override_file, name
+        );
+        return Ok(None);
+    }
+    debug!("Saving the bundled definition of '{}'", name);
+    if is_dry_run() {
+        save_json(index, &override_file)?;
+        return Ok(None);
+    }
+    fs::create_dir_all(config.overrides_path())?;
+    save_json(index, &override_file)?;
+    Ok(Some(override_file))
+}
+
+#[test]
+fn verify_bundle_checksums() {
+    let bundle = |image_data: &[u8], tamper: bool| {
+        let mut builder = Builder::new(io::Cursor::new(Vec::new()));
+        let mut header = entry_header(0);
+        let mut entry = builder
+            .append_writer(&mut header, "images/busybox-latest.tar")
+            .unwrap();
+        let mut writer = HashingWriter {
+            inner: &mut entry,
+            hasher: Sha256::new(),
+        };
+        writer.write_all(image_data).unwrap();
+        let mut sha256 = format!("{:x}", writer.hasher.finalize());
+        entry.finish().unwrap();
+        if tamper {
+            sha256 = sha256.replace(|c: char| c.is_ascii_digit(), "0");
+        }
+
+        let manifest = Manifest {
+            format: BUNDLE_FORMAT,
+            created_at: String::new(),
+            packages: vec![BundledPackage {
+                name: "busybox".to_string(),
+                current: "latest".to_string(),
+                index: PackageIndex::new("busybox"),
+                images: vec![BundledImage {
+                    version: "latest".to_string(),
+                    image: "docker.io/busybox:latest".to_string(),
+                    file: "images/busybox-latest.tar".to_string(),
+                    sha256,
+                }],
+            }],
+        };
+        let contents = serde_json::to_vec(&manifest).unwrap();
+        let mut header = entry_header(0);
+        header.set_size(contents.len() as u64);
+        builder
+            .append_data(&mut header, MANIFEST_FILE, contents.as_slice())
+            .unwrap();
+        builder.into_inner().unwrap().into_inner()
+    };
+
+    let image_data = vec![7u8; 3000];
+    let valid = bundle(&image_data, false);
+    let manifest = verify_bundle(&mut Archive::new(valid.as_slice())).unwrap();
+    assert_eq!(
+        manifest.packages[0].images[0].sha256,
+        format!("{:x}", Sha256::digest(&image_data))
+    );
+
+    let tampered = bundle(&image_data, true);
+    let error = verify_bundle(&mut Archive::new(tampered.as_slice())).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Checksum mismatch for image 'docker.io/busybox:latest'."
+    );
+}
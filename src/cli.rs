@@ -1,7 +1,18 @@
+use crate::activation::{activate, deactivate};
+use crate::bundle::{create_bundle, install_bundle};
 use crate::commands::*;
-use crate::logging::setup_logger;
-use clap::{Parser, Subcommand};
+use crate::completions::{complete, generate, Shell};
+use crate::dry_run::set_dry_run;
+use crate::lock::StateLock;
+use crate::logging::{redact_env_args, setup_logger};
+use crate::output::{set_quiet, OutputFormat};
+use crate::report;
+use crate::runner::BuildOptions;
+use crate::shims::{find_shim, find_shim_path, reshim, shim_name};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
 use log::{debug, error};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::process;
 
 #[derive(Parser)]
@@ -9,6 +20,22 @@ use std::process;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Output format for commands that print results
+    #[arg(long, short, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    /// Print logs to stderr (-v: info, -vv: debug, -vvv: trace)
+    #[arg(long, short, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print errors and requested data
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print the planned file writes, shim changes and engine commands without performing them
+    #[arg(long, global = true)]
+    dry_run: bool,
 }
 
 #[derive(Subcommand)]
@@ -23,22 +50,28 @@ enum Commands {
 
         /// Show additional information, like image, volumes, binaries, etc.
         #[arg(long, short)]
-        verbose: bool,
+        long: bool,
     },
 
     /// Add and install a specific version of a package
     #[command(alias = "install")]
     Add {
-        /// Name of the package to install
-        name: String,
+        /// Packages to install, as NAME or NAME@VERSION (default version: latest). A single package
+        /// can also be given as NAME VERSION
+        #[arg(required_unless_present = "project", value_name = "NAME[@VERSION]")]
+        packages: Vec<String>,
 
-        /// Version of the package (default: latest)
-        #[arg(default_value = "latest")]
-        version: String,
+        /// Also install the versions pinned in the closest project file
+        #[arg(long)]
+        project: bool,
 
         /// Set the added version as the current default version
         #[arg(long, short)]
         set_default: bool,
+
+        /// Maximum number of images to pull or build at the same time
+        #[arg(long, short, default_value_t = 4)]
+        jobs: usize,
     },
 
     /// Remove a specific version of a package
@@ -64,12 +97,104 @@ enum Commands {
     /// Run a command from a package
     #[command(disable_help_flag = true)]
     Run {
-        /// Name of the package to run
+        /// Environment variables to set in the container, as KEY=VALUE or KEY to pass it from the host
+        #[arg(long = "env", short = 'e', value_name = "KEY[=VALUE]")]
+        environment: Vec<String>,
+
+        /// Name of the package to run, followed by the arguments to pass to the package's command.
+        /// Everything after the name goes to the package, even flags also known by hbox.
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            value_names = ["NAME", "ARGS"]
+        )]
+        command: Vec<String>,
+    },
+
+    /// Recreate the shims of all installed packages
+    Reshim,
+
+    /// Build the image of a locally built package again
+    Rebuild {
+        /// Name of the package to rebuild
         name: String,
 
-        /// Arguments to pass to the package's command
+        /// Version to rebuild (default: current version)
+        version: Option<String>,
+
+        /// Build without using the cache of the container engine
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Always pull newer versions of the base images
+        #[arg(long)]
+        pull: bool,
+    },
+
+    /// Show which package and version a command resolves to, and the engine command it runs
+    #[command(visible_alias = "exec-path")]
+    Which {
+        /// Name of the command (shim), package or `package::binary` to look up, followed by the
+        /// arguments to include in the engine command
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            value_names = ["COMMAND", "ARGS"]
+        )]
+        command: Vec<String>,
+    },
+
+    /// Export packages and their images into a bundle file, or install them from one
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+
+    /// Show usage statistics, like most used packages and unused versions
+    Stats {
+        /// Maximum number of entries to show in each section
+        #[arg(long, short, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Print shell code defining functions for installed packages, as an alternative to shims
+    Activate {
+        /// Shell to generate the activation code for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Print shell code removing everything defined by 'activate'
+    Deactivate {
+        /// Shell to generate the deactivation code for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Generate shell completions, including installed packages and versions
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Print completion candidates for the given words (used by completion scripts)
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// Words typed after 'hbox', the last one being completed
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        subcommand: Vec<String>,
+        words: Vec<String>,
+    },
+
+    /// Show hbox logs
+    Logs {
+        /// Keep printing new log entries as they are written
+        #[arg(long, short)]
+        follow: bool,
+
+        /// Only show log entries for a specific package
+        #[arg(long, short)]
+        package: Option<String>,
     },
 
     /// Configure hbox settings
@@ -83,41 +208,185 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Save the images, versions and definitions of installed packages into a bundle file
+    Create {
+        /// Packages to include, as NAME (all installed versions) or NAME@VERSION
+        #[arg(required = true, value_name = "NAME[@VERSION]")]
+        packages: Vec<String>,
+
+        /// Path of the bundle file to create (`-o` is the global output format option)
+        #[arg(long, short)]
+        file: PathBuf,
+    },
+
+    /// Load the images of a bundle file and add its packages
+    Install {
+        /// Path of the bundle file
+        file: PathBuf,
+    },
+}
+
+impl Commands {
+    /// Indicates if the command writes to `HBOX_DIR`, so it must hold the state lock.
+    fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            Commands::Add { .. }
+                | Commands::Remove { .. }
+                | Commands::Use { .. }
+                | Commands::Reshim
+                | Commands::Rebuild { .. }
+                | Commands::Bundle {
+                    command: BundleCommands::Install { .. }
+                }
+                | Commands::Config { value: Some(_), .. }
+        )
+    }
+}
+
 pub fn run() {
-    if let Err(e) = setup_logger() {
+    if let Some(command) = shim_command() {
+        if let Err(e) = setup_logger(0, false) {
+            eprintln!("Could not setup logger: {}", e);
+            process::exit(1);
+        }
+        debug!("Running shim {}", command);
+        let params = std::env::args().skip(1).collect();
+        let exit_code = exit_on_error(run_package(command, params, Vec::new()));
+        process::exit(exit_code);
+    }
+
+    let cli = Cli::parse();
+
+    if let Err(e) = setup_logger(cli.verbose, cli.quiet) {
         eprintln!("Could not setup logger: {}", e);
         process::exit(1);
     }
+    set_quiet(cli.quiet);
+    set_dry_run(cli.dry_run);
 
+    // Values given with `-e`, like `-e TOKEN=...`, may be secrets. The trailing package command
+    // is left as is, as its options belong to the package.
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let package_args = match &cli.command {
+        Commands::Run { command, .. } | Commands::Which { command, .. } => {
+            args.split_off(args.len().saturating_sub(command.len()))
+        }
+        _ => Vec::new(),
+    };
     debug!(
         "hbox {}",
-        std::env::args().skip(1).collect::<Vec<String>>().join(" ")
+        [redact_env_args(&args), package_args].concat().join(" ")
     );
 
-    let cli = Cli::parse();
+    let _lock = if cli.command.changes_state() && !cli.dry_run {
+        match StateLock::acquire() {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                error!("Could not lock hbox directory: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
+    // Exit code of the command run by `hbox run`, which hbox exits with.
+    let mut exit_code = 0;
     let result = match &cli.command {
-        Commands::Info => show_info(),
-        Commands::List { name, verbose } => list_packages(name.as_deref(), *verbose),
+        Commands::Info => show_info(cli.output),
+        Commands::List { name, long } => list_packages(name.as_deref(), *long, cli.output),
         Commands::Add {
-            name,
-            version,
+            packages,
+            project,
             set_default,
-        } => add_package(name.clone(), version.clone(), *set_default),
+            jobs,
+        } => add_packages(packages, *project, *set_default, *jobs),
         Commands::Remove { name, version } => remove_package(name.clone(), version.clone()),
         Commands::Use { name, version } => use_package_version(name.clone(), version.clone()),
-        Commands::Run { name, subcommand } => run_package(name.clone(), subcommand.clone()),
-        Commands::Config { path, value } => configure_setting(path.clone(), value.clone()),
+        Commands::Run {
+            environment,
+            command,
+        } => {
+            let (name, params) = command.split_first().expect("command is required");
+            run_package(name.clone(), params.to_vec(), environment.clone())
+                .map(|code| exit_code = code)
+        }
+        Commands::Activate { shell } => activate(*shell).map(|script| print!("{}", script)),
+        Commands::Deactivate { shell } => {
+            print!("{}", deactivate(*shell));
+            Ok(())
+        }
+        Commands::Completions { shell } => {
+            print!("{}", generate(*shell));
+            Ok(())
+        }
+        Commands::Complete { words } => {
+            for candidate in complete(&Cli::command(), words) {
+                println!("{}", candidate);
+            }
+            Ok(())
+        }
+        Commands::Reshim => reshim(),
+        Commands::Rebuild {
+            name,
+            version,
+            no_cache,
+            pull,
+        } => rebuild_package(
+            name.clone(),
+            version.clone(),
+            BuildOptions {
+                no_cache: *no_cache,
+                pull: *pull,
+            },
+        ),
+        Commands::Which { command } => {
+            let (name, params) = command.split_first().expect("command is required");
+            show_which(name, params, cli.output)
+        }
+        Commands::Bundle { command } => match command {
+            BundleCommands::Create { packages, file } => create_bundle(packages, file),
+            BundleCommands::Install { file } => install_bundle(file),
+        },
+        Commands::Stats { limit } => show_stats(*limit, cli.output),
+        Commands::Logs { follow, package } => show_logs(*follow, package.clone()),
+        Commands::Config { path, value } => {
+            configure_setting(path.clone(), value.clone(), cli.output)
+        }
     };
 
-    if let Err(e) = result {
+    exit_on_error(result);
+    if cli.dry_run {
+        report!("Dry run, no changes were made.");
+    }
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
+}
+
+/// When hbox is invoked through a link shim, returns the command the shim should run.
+fn shim_command() -> Option<String> {
+    let program = std::env::args_os().next()?;
+    let name = shim_name(Path::new(&program))?.to_string();
+    // Only look up the shim when hbox runs through one, so a renamed hbox binary doesn't pay for
+    // loading packages.
+    if name == env!("CARGO_PKG_NAME") || find_shim_path(&name).is_none() {
+        return None;
+    }
+    find_shim(&name).map(|shim| shim.command())
+}
+
+fn exit_on_error<T>(result: Result<T, Box<dyn Error>>) -> T {
+    result.unwrap_or_else(|e| {
         error!("{}", e);
         process::exit(1);
-    }
+    })
 }
 
 #[test]
 fn verify_cli() {
-    use clap::CommandFactory;
     Cli::command().debug_assert()
 }
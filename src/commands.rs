@@ -1,116 +1,238 @@
 use crate::configs::app::AppConfig;
+use crate::configs::context::Context;
+use crate::configs::project::{ProjectConfig, PROJECT_FILE_NAME};
+use crate::configs::shim::ShimConfig;
 use crate::configs::user::UserConfig;
 use crate::configs::version::VersionConfig;
-use crate::packages::Package;
-use crate::runner::run;
-use crate::shims::{add_shim, remove_shim};
-use log::info;
+use crate::dry_run::{command_line, is_dry_run};
+use crate::history::{History, Invocation, Stats};
+use crate::logging::{log_file_path, log_line_has_package, set_log_package};
+use crate::output::{print_structured, OutputFormat};
+use crate::packages::{Package, VersionSource};
+use crate::report;
+use crate::runner::{build, build_hash, dry_run, image_digest, image_reference, run, BuildOptions};
+use crate::shims::{add_shim, find_shim, find_shim_path, remove_shim};
+use crate::transaction::{Change, Transaction};
+use chrono::Local;
+use log::debug;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn show_info() -> Result<(), Box<dyn Error>> {
+#[derive(Serialize)]
+struct Info {
+    os: OsInfo,
+    version: String,
+    engine: String,
+    paths: PathsInfo,
+    environment: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct OsInfo {
+    name: String,
+    arch: String,
+    family: String,
+}
+
+#[derive(Serialize)]
+struct PathsInfo {
+    base_dir: PathBuf,
+    config_file: PathBuf,
+    overrides_dir: PathBuf,
+    versions_dir: PathBuf,
+    logs_dir: PathBuf,
+    shims_dir: PathBuf,
+    index_dir: PathBuf,
+    homes_dir: PathBuf,
+}
+
+pub fn show_info(format: OutputFormat) -> Result<(), Box<dyn Error>> {
     let config = AppConfig::load();
     let user_config = UserConfig::load().unwrap_or_default();
-    info!("");
-    info!("[System Information]");
-    info!("OS Details:");
-    info!("  Name           : {}", env::consts::OS);
-    info!("  Architecture   : {}", env::consts::ARCH);
-    info!("  Family         : {}", env::consts::FAMILY);
-    info!("");
-    info!("[Application Configuration]");
-    info!("Version          : {}", env!("CARGO_PKG_VERSION"));
-    info!("Engine           : {}", user_config.engine.as_str());
-    info!("Directories and Files:");
-    info!(
+
+    if format.is_structured() {
+        let info = Info {
+            os: OsInfo {
+                name: env::consts::OS.to_string(),
+                arch: env::consts::ARCH.to_string(),
+                family: env::consts::FAMILY.to_string(),
+            },
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            engine: user_config.engine.as_str().to_string(),
+            paths: PathsInfo {
+                base_dir: config.base_dir.clone(),
+                config_file: config.config_file_path(),
+                overrides_dir: config.overrides_path(),
+                versions_dir: config.versions_path(),
+                logs_dir: config.logs_path(),
+                shims_dir: config.shims_path(),
+                index_dir: config.index_path(),
+                homes_dir: config.homes_path(),
+            },
+            environment: HashMap::from([(
+                "HBOX_DIR".to_string(),
+                config.base_dir.display().to_string(),
+            )]),
+        };
+        return print_structured(&info, format);
+    }
+
+    report!("");
+    report!("[System Information]");
+    report!("OS Details:");
+    report!("  Name           : {}", env::consts::OS);
+    report!("  Architecture   : {}", env::consts::ARCH);
+    report!("  Family         : {}", env::consts::FAMILY);
+    report!("");
+    report!("[Application Configuration]");
+    report!("Version          : {}", env!("CARGO_PKG_VERSION"));
+    report!("Engine           : {}", user_config.engine.as_str());
+    report!("Directories and Files:");
+    report!(
         "  base dir       : {}",
-        config.base_dir.to_str().unwrap_or_else(|| "")
+        config.base_dir.to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  config file    : {}",
-        config.config_file_path().to_str().unwrap_or_else(|| "")
+        config.config_file_path().to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  overrides dir  : {}",
-        config.overrides_path().to_str().unwrap_or_else(|| "")
+        config.overrides_path().to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  versions dir   : {}",
-        config.versions_path().to_str().unwrap_or_else(|| "")
+        config.versions_path().to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  logs dir       : {}",
-        config.logs_path().to_str().unwrap_or_else(|| "")
+        config.logs_path().to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  shims dir      : {}",
-        config.shims_path().to_str().unwrap_or_else(|| "")
+        config.shims_path().to_str().unwrap_or("")
     );
-    info!(
+    report!(
         "  index dir      : {}",
-        config.index_path().to_str().unwrap_or_else(|| "")
+        config.index_path().to_str().unwrap_or("")
     );
-    info!("Environment Vars:");
-    info!(
+    report!(
+        "  homes dir      : {}",
+        config.homes_path().to_str().unwrap_or("")
+    );
+    report!("Environment Vars:");
+    report!(
         "  HBOX_DIR       : {}",
-        config.base_dir.to_str().unwrap_or_else(|| "")
+        config.base_dir.to_str().unwrap_or("")
     );
     Ok(())
 }
 
-pub fn list_packages(name: Option<&str>, verbose: bool) -> Result<(), Box<dyn Error>> {
-    if let Some(name) = name {
+pub fn list_packages(
+    name: Option<&str>,
+    verbose: bool,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let packages = if let Some(name) = name {
         if let Some(package) = Package::load(name)? {
-            package.print(verbose);
-            Ok(())
+            vec![package]
         } else {
-            Err(format!(
+            return Err(format!(
                 "Package '{}' was not found. Add the package first via 'add' command.",
                 name
             )
-            .into())
+            .into());
         }
     } else {
-        let packages = Package::load_all()?;
-        if !packages.is_empty() {
-            for package in &packages {
-                package.print(verbose);
-            }
-            Ok(())
-        } else {
-            Err("Could not find any packages installed.".into())
+        Package::load_all()?
+    };
+
+    if format.is_structured() {
+        let shims = ShimConfig::load()?;
+        let summaries = packages
+            .iter()
+            .map(|p| p.summary(verbose, &shims))
+            .collect::<Result<Vec<_>, _>>()?;
+        print_structured(&summaries, format)
+    } else if !packages.is_empty() {
+        for package in &packages {
+            package.print(verbose);
         }
+        Ok(())
+    } else {
+        Err("Could not find any packages installed.".into())
     }
 }
 
-pub fn add_package(name: String, version: String, set_default: bool) -> Result<(), Box<dyn Error>> {
-    if let Some(mut package) = Package::load(&name)? {
-        if package.versions.versions.contains(&version) {
-            return Err(format!("'{}' version {} already exists.", name, version).into());
-        } else {
-            package.versions.versions.push(version.clone());
-        }
-        if set_default {
-            package.versions.current = version.clone();
+pub fn add_packages(
+    specs: &[String],
+    from_project: bool,
+    set_default: bool,
+    jobs: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut requests = parse_package_specs(specs)?;
+    if from_project {
+        let (project_file, project) = ProjectConfig::load()?.ok_or_else(|| {
+            format!(
+                "Could not find a {} file in the current directory or its parents.",
+                PROJECT_FILE_NAME
+            )
+        })?;
+        debug!("Adding packages pinned in {:?}", project_file);
+        let mut pinned: Vec<_> = project.packages.into_iter().collect();
+        pinned.sort();
+        for (name, version) in pinned {
+            let installed = VersionConfig::load(name.clone())?
+                .is_some_and(|package| package.versions.contains(&version));
+            if installed {
+                report!("'{}' version '{}' is already installed.", name, version);
+            } else {
+                requests.push((name, version));
+            }
         }
-        let current = package.versions.current.clone();
-        do_add_package(&name, &version, package)?;
-        info!(
-            "Added '{}' version '{}'. Current version is '{}'.",
-            name, version, current
-        );
-    } else {
-        let package = Package::new(&name, crate::configs::version::Package::new(&version))?;
-        do_add_package(&name, &version, package)?;
-        info!(
-            "Added '{}' version '{}'. Current version is '{}'.",
-            name, version, version
-        );
     }
+    remove_duplicates(&mut requests);
+
+    match requests.as_slice() {
+        [] if from_project => Ok(()),
+        [] => Err("No packages to add.".into()),
+        [(name, version)] => add_package(name.clone(), version.clone(), set_default),
+        _ => add_many_packages(requests, set_default, jobs),
+    }
+}
+
+/// Removes the packages requested more than once, like in `hbox add jq node jq`, keeping the
+/// order of the first requests.
+fn remove_duplicates(requests: &mut Vec<(String, String)>) {
+    let mut seen = HashSet::new();
+    requests.retain(|request| seen.insert(request.clone()));
+}
+
+pub fn add_package(name: String, version: String, set_default: bool) -> Result<(), Box<dyn Error>> {
+    set_log_package(&name, &version);
+    let package = prepare_package(&name, &version, set_default)?;
+    let current = package.versions.current.clone();
+    do_add_package(&name, &version, package)?;
+    report!(
+        "Added '{}' version '{}'. Current version is '{}'.",
+        name,
+        version,
+        current
+    );
     Ok(())
 }
 
 pub fn remove_package(name: String, version: Option<String>) -> Result<(), Box<dyn Error>> {
+    set_log_package(&name, version.as_deref().unwrap_or("*"));
     match (Package::load(&name)?, version) {
         (Some(mut package), Some(version)) => {
             if package.versions.current == version && package.versions.versions.len() > 1 {
@@ -122,12 +244,13 @@ pub fn remove_package(name: String, version: Option<String>) -> Result<(), Box<d
             } else {
                 if package.versions.versions.contains(&version) {
                     package.versions.versions.retain(|v| v != version.as_str());
+                    package.versions.builds.remove(&version);
                     if package.versions.versions.is_empty() {
                         do_remove_package(package)?;
-                        info!("Removed package '{}'.", name);
+                        report!("Removed package '{}'.", name);
                     } else {
                         VersionConfig::upsert(&name, package)?;
-                        info!("Removed version '{}' of '{}'.", version, name);
+                        report!("Removed version '{}' of '{}'.", version, name);
                     }
                     Ok(())
                 } else {
@@ -137,7 +260,7 @@ pub fn remove_package(name: String, version: Option<String>) -> Result<(), Box<d
         }
         (Some(package), None) => {
             do_remove_package(package)?;
-            info!("Removed package '{}'.", name);
+            report!("Removed package '{}'.", name);
             Ok(())
         }
         (None, _) => Err(format!("Package '{}' does not exists.", name).into()),
@@ -145,11 +268,12 @@ pub fn remove_package(name: String, version: Option<String>) -> Result<(), Box<d
 }
 
 pub fn use_package_version(name: String, version: String) -> Result<(), Box<dyn Error>> {
+    set_log_package(&name, &version);
     if let Some(mut package) = Package::load(&name)? {
         if package.versions.versions.contains(&version) {
             package.versions.current = version.clone();
             VersionConfig::upsert(&name, package)?;
-            info!("Package '{}' set to version '{}'", name, version);
+            report!("Package '{}' set to version '{}'", name, version);
             Ok(())
         } else {
             Err(format!(
@@ -163,7 +287,44 @@ pub fn use_package_version(name: String, version: String) -> Result<(), Box<dyn
     }
 }
 
-pub fn run_package(name: String, subcommand: Vec<String>) -> Result<(), Box<dyn Error>> {
+pub fn rebuild_package(
+    name: String,
+    version: Option<String>,
+    options: BuildOptions,
+) -> Result<(), Box<dyn Error>> {
+    let Some(mut package) = Package::load(&name)? else {
+        return Err(format!("Package '{}' does not exists.", name).into());
+    };
+    if !package.index.image.is_local() {
+        return Err(format!("Package '{}' doesn't build its image locally.", name).into());
+    }
+    // Builds reuse the local image in offline mode, so nothing would be rebuilt.
+    if UserConfig::load().unwrap_or_default().offline {
+        return Err(format!("Can't rebuild '{}' while hbox is in offline mode.", name).into());
+    }
+    let version = version.unwrap_or_else(|| package.versions.current.clone());
+    if !package.versions.versions.contains(&version) {
+        return Err(format!("Version '{}' of '{}' does not exists.", version, name).into());
+    }
+    set_log_package(&name, &version);
+
+    package.versions.current = version.clone();
+    let hash = build_hash(&package)?;
+    if !build(&package, options, false)? {
+        return Err(format!("Failed to rebuild '{}' version '{}'.", name, version).into());
+    }
+    if let Some(hash) = hash {
+        VersionConfig::save_build_hash(&name, &version, hash)?;
+    }
+    report!("Rebuilt '{}' version '{}'.", name, version);
+    Ok(())
+}
+
+pub fn run_package(
+    name: String,
+    subcommand: Vec<String>,
+    environment: Vec<String>,
+) -> Result<i32, Box<dyn Error>> {
     let parts: Vec<&str> = name.split("::").collect();
     let (package_name, binary) = match parts.as_slice() {
         [package_name] => (package_name.to_string(), None),
@@ -171,59 +332,422 @@ pub fn run_package(name: String, subcommand: Vec<String>) -> Result<(), Box<dyn
         _ => return Err(format!("Invalid package name '{}'.", name).into()),
     };
 
-    if let Some(package) = Package::load(&package_name)? {
-        run(&package, binary, &subcommand);
-        Ok(())
+    if let Some(mut package) = Package::load(&package_name)? {
+        package.apply_version_pin()?;
+        set_log_package(&package.name, &package.versions.current);
+        let started_at = Instant::now();
+        let exit_code = run(&package, binary.clone(), &subcommand, &environment)?;
+        if is_dry_run() {
+            return Ok(exit_code);
+        }
+        History::record(&Invocation {
+            timestamp: Local::now(),
+            package: package.name.clone(),
+            binary,
+            version: package.versions.current.clone(),
+            exit_code,
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            cwd: env::current_dir().ok(),
+        });
+        Ok(exit_code)
     } else {
         Err(format!("Package '{}' does not exists.", name).into())
     }
 }
 
-pub fn configure_setting(path: String, value: Option<String>) -> Result<(), Box<dyn Error>> {
+pub fn show_stats(limit: usize, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let stats = Stats::from(&History::load()?, &Package::load_all()?, limit);
+    if format.is_structured() {
+        return print_structured(&stats, format);
+    }
+
+    report!("[Most Used]");
+    for usage in &stats.most_used {
+        let name = match &usage.binary {
+            Some(binary) => format!("{}::{}", usage.package, binary),
+            None => usage.package.clone(),
+        };
+        report!(
+            "  {:<24} : {} runs, {} failed, last used {}",
+            name,
+            usage.runs,
+            usage.failures,
+            usage.last_used.format("%Y-%m-%d %H:%M")
+        );
+    }
+    report!("");
+    report!("[Unused Versions]");
+    for unused in &stats.unused_versions {
+        let current = if unused.current { " ✔" } else { "" };
+        report!("  {:<24} : {}{}", unused.package, unused.version, current);
+    }
+    report!("");
+    report!("[Slowest Startups]");
+    for startup in &stats.slowest_startups {
+        report!(
+            "  {:<24} : {} ms ({} runs)",
+            format!("{}@{}", startup.package, startup.version),
+            startup.startup_ms,
+            startup.runs
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Which {
+    command: String,
+    package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary: Option<String>,
+    version: String,
+    version_source: VersionSource,
+    image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shim: Option<PathBuf>,
+    engine_command: Vec<String>,
+}
+
+pub fn show_which(
+    command: &str,
+    params: &[String],
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let (name, binary) = match find_shim(command) {
+        Some(shim) => (shim.package, shim.binary),
+        None => match command.split_once("::") {
+            Some((name, binary)) => (name.to_string(), Some(binary.to_string())),
+            None => (command.to_string(), None),
+        },
+    };
+    let mut package = Package::load(&name)?.ok_or_else(|| {
+        format!(
+            "'{}' isn't a shim or package of any installed package.",
+            command
+        )
+    })?;
+    let version_source = package.apply_version_pin()?;
+    let context = Context::from(&package).with_binary(binary.as_deref());
+    let image = image_reference(&package, &context)?;
+    let engine_command = dry_run(&package, binary.clone(), params, &[])?;
+
+    let which = Which {
+        command: command.to_string(),
+        package: package.name.clone(),
+        binary,
+        version: package.versions.current.clone(),
+        version_source,
+        image,
+        shim: find_shim_path(command),
+        engine_command,
+    };
+    if format.is_structured() {
+        return print_structured(&which, format);
+    }
+
+    report!("command : {}", which.command);
+    report!("package : {}", which.package);
+    if let Some(binary) = &which.binary {
+        report!("binary  : {}", binary);
+    }
+    report!("version : {} ({})", which.version, which.version_source);
+    report!("image   : {}", which.image);
+    if let Some(shim) = &which.shim {
+        report!("shim    : {}", shim.display());
+    }
+    let (engine, args) = which
+        .engine_command
+        .split_first()
+        .ok_or("Missing engine command")?;
+    report!("engine  : {}", command_line(engine, args));
+    Ok(())
+}
+
+pub fn configure_setting(
+    path: String,
+    value: Option<String>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
     if let Some(value) = value {
         UserConfig::write_config_value(&path, &value)?;
     } else {
-        UserConfig::read_config_value(&path)?;
+        let value = UserConfig::read_config_value(&path)?;
+        if format.is_structured() {
+            print_structured(&value, format)?;
+        } else if value.is_object() {
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        } else {
+            println!("{}", value);
+        }
     }
 
     Ok(())
 }
 
-fn do_add_package(name: &String, version: &String, package: Package) -> Result<(), Box<dyn Error>> {
+pub fn show_logs(follow: bool, package: Option<String>) -> Result<(), Box<dyn Error>> {
+    let logs_config = UserConfig::load().unwrap_or_default().logs;
+    let path = log_file_path(&logs_config);
+    if !path.exists() {
+        return Err("No logs found. Enable logs via 'hbox config logs.enabled true'.".into());
+    }
+
+    let mut reader = BufReader::new(File::open(&path)?);
+    let mut position = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read > 0 && line.ends_with('\n') {
+            position += read as u64;
+            let entry = line.trim_end();
+            if package
+                .as_ref()
+                .is_none_or(|p| log_line_has_package(entry, &logs_config.format, p))
+            {
+                println!("{}", entry);
+            }
+            continue;
+        }
+        if !follow {
+            break;
+        }
+
+        // Wait for a complete line, reopening the file if it was rotated or truncated.
+        reader.seek(SeekFrom::Start(position))?;
+        thread::sleep(Duration::from_millis(500));
+        if fs::metadata(&path).map_or(true, |m| m.len() < position) {
+            if let Ok(file) = File::open(&path) {
+                reader = BufReader::new(file);
+                position = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds several packages, pulling or building their images concurrently, at most `jobs` at a
+/// time. Shims and versions files are then updated one package at a time.
+fn add_many_packages(
+    requests: Vec<(String, String)>,
+    set_default: bool,
+    jobs: usize,
+) -> Result<(), Box<dyn Error>> {
+    let total = requests.len();
+    let prepared: Vec<_> = requests
+        .into_iter()
+        .map(|(name, version)| {
+            let package = prepare_package(&name, &version, set_default).map_err(|e| e.to_string());
+            (name, version, package)
+        })
+        .collect();
+
+    report!("Adding {} packages, {} at a time...", total, jobs.max(1));
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let images = Mutex::new(vec![None; total]);
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, total) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some((name, version, package)) = prepared.get(index) else {
+                    break;
+                };
+                let result = match package {
+                    Ok(package) => fetch_image(name, version, package, true),
+                    Err(e) => Err(e.clone().into()),
+                };
+                let result = result.map_err(|e| e.to_string());
+                let status = if result.is_ok() { "ready" } else { "failed" };
+                let count = done.fetch_add(1, Ordering::SeqCst) + 1;
+                report!("[{}/{}] {}@{} {}", count, total, name, version, status);
+                images.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let mut failures = 0;
+    let mut results = Vec::new();
+    let images = images.into_inner().unwrap();
+    for ((name, version, package), image) in prepared.into_iter().zip(images) {
+        let result = match (package, image) {
+            // Prepared again, since an earlier request may have added another version of it.
+            (Ok(_), Some(Ok(new_image))) => prepare_package(&name, &version, set_default)
+                .and_then(|package| install_package(&name, package, new_image))
+                .map_err(|e| e.to_string()),
+            (_, Some(Err(e))) => Err(e),
+            (Err(e), _) => Err(e),
+            (Ok(_), None) => Err("Image was not pulled".to_string()),
+        };
+        if result.is_err() {
+            failures += 1;
+        }
+        results.push((name, version, result));
+    }
+
+    report!("");
+    for (name, version, result) in &results {
+        match result {
+            Ok(()) => report!("  ✔ {}@{}", name, version),
+            Err(e) => report!("  ✘ {}@{}: {}", name, version, e),
+        }
+    }
+    if failures > 0 {
+        return Err(format!("Failed to add {} of {} packages.", failures, total).into());
+    }
+    Ok(())
+}
+
+/// Splits `NAME[@VERSION]` package specs into names and versions. Exactly two specs without
+/// `@`, as in `hbox add node 18`, are a package name and its version.
+fn parse_package_specs(specs: &[String]) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if let [name, version] = specs {
+        if !name.contains('@') && !version.contains('@') {
+            return Ok(vec![(name.clone(), version.clone())]);
+        }
+    }
+    specs
+        .iter()
+        .map(|spec| match spec.split_once('@') {
+            Some((name, version)) if name.is_empty() || version.is_empty() => {
+                Err(format!("Invalid package '{}', use NAME@VERSION.", spec).into())
+            }
+            Some((name, version)) => Ok((name.to_string(), version.to_string())),
+            None => Ok((spec.clone(), "latest".to_string())),
+        })
+        .collect()
+}
+
+/// Returns the package with `version` added, failing if it's already installed.
+pub fn prepare_package(
+    name: &str,
+    version: &str,
+    set_default: bool,
+) -> Result<Package, Box<dyn Error>> {
+    if let Some(mut package) = Package::load(name)? {
+        if package.versions.versions.iter().any(|v| v == version) {
+            return Err(format!("'{}' version {} already exists.", name, version).into());
+        }
+        package.versions.versions.push(version.to_string());
+        if set_default {
+            package.versions.current = version.to_string();
+        }
+        Ok(with_build_hash(package, version))
+    } else {
+        let package = Package::new(name, crate::configs::version::Package::new(version))?;
+        Ok(with_build_hash(package, version))
+    }
+}
+
+/// Records what a local image is built from, so `run` can tell when it must be rebuilt.
+fn with_build_hash(mut package: Package, version: &str) -> Package {
+    let mut versioned = package.clone();
+    versioned.versions.current = version.to_string();
+    match build_hash(&versioned) {
+        Ok(Some(hash)) => {
+            package.versions.builds.insert(version.to_string(), hash);
+        }
+        Ok(None) => {}
+        Err(e) => debug!("Could not hash the build of '{}': {}", package.name, e),
+    }
+    package
+}
+
+fn do_add_package(name: &str, version: &str, package: Package) -> Result<(), Box<dyn Error>> {
+    let new_image = fetch_image(name, version, &package, false)?;
+    install_package(name, package, new_image)
+}
+
+/// Pulls or builds the image of the version being added. Returns the image reference if it
+/// wasn't available before, so it can be removed if adding the package fails.
+fn fetch_image(
+    name: &str,
+    version: &str,
+    package: &Package,
+    quiet: bool,
+) -> Result<Option<String>, Box<dyn Error>> {
     let mut new_package = package.clone();
-    new_package.versions.current = version.clone();
+    new_package.versions.current = version.to_string();
+    let image = image_reference(&new_package, &Context::from(&new_package))?;
+    let is_new_image = image_digest(&image).is_none();
 
     let should_add_package = if new_package.index.image.is_local() {
-        crate::runner::build(&new_package)
+        build(&new_package, BuildOptions::default(), quiet)?
     } else {
-        crate::runner::pull(&new_package)
+        crate::runner::pull(&new_package, quiet)?
     };
+    if !should_add_package {
+        return Err(format!("Failed to add package '{}' at version '{}'.", name, version).into());
+    }
+    Ok(is_new_image.then_some(image))
+}
 
-    if should_add_package {
-        if !&package.index.only_shim_binaries {
-            add_shim(&name, None)?;
+pub fn install_package(
+    name: &str,
+    package: Package,
+    new_image: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let remove_on_rollback = UserConfig::load()
+        .unwrap_or_default()
+        .images
+        .remove_on_rollback;
+    Transaction::run(|transaction| {
+        if let Some(image) = new_image.filter(|_| remove_on_rollback) {
+            transaction.record(Change::ImageAdded(image));
         }
-        if let Some(binaries) = &package.index.binaries {
-            for binary in binaries {
-                add_shim(&name, Some(&binary.name))?;
+        for shim in package.shim_names() {
+            if let Some(shim_name) = add_shim(&package, &shim)? {
+                transaction.record(Change::ShimCreated(shim_name));
             }
         }
-        VersionConfig::upsert(&name, package)?;
+        let previous = VersionConfig::load(name.to_string())?;
+        VersionConfig::upsert(name, package)?;
+        transaction.record(Change::VersionsChanged(name.to_string(), previous));
         Ok(())
-    } else {
-        Err(format!("Failed to add package '{}' at version '{}'.", name, version).into())
-    }
+    })
 }
 
 fn do_remove_package(package: Package) -> Result<(), Box<dyn Error>> {
-    VersionConfig::remove(&package.name)?;
-    if !&package.index.only_shim_binaries {
-        remove_shim(&package.name)?;
-    }
-    if let Some(binaries) = &package.index.binaries {
-        for binary in binaries {
-            remove_shim(&binary.name)?;
+    Transaction::run(|transaction| {
+        for shim in package.shim_names() {
+            if remove_shim(&package, &shim)? {
+                transaction.record(Change::ShimRemoved(Box::new(package.clone()), shim));
+            }
         }
-    }
-    Ok(())
+        VersionConfig::remove(&package.name)?;
+        Ok(())
+    })
+}
+
+#[test]
+fn verify_package_specs() {
+    let specs = |specs: &[&str]| {
+        parse_package_specs(&specs.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap()
+    };
+    let pair = |name: &str, version: &str| (name.to_string(), version.to_string());
+    assert_eq!(specs(&["node", "v18"]), vec![pair("node", "v18")]);
+    assert_eq!(specs(&["golang", "alpine"]), vec![pair("golang", "alpine")]);
+    assert_eq!(
+        specs(&["jq", "yq", "node@18"]),
+        vec![
+            pair("jq", "latest"),
+            pair("yq", "latest"),
+            pair("node", "18")
+        ]
+    );
+    assert_eq!(
+        specs(&["jq", "node@18"]),
+        vec![pair("jq", "latest"), pair("node", "18")]
+    );
+    assert!(parse_package_specs(&["node@".to_string()]).is_err());
+
+    let mut requests = specs(&["jq", "node", "jq", "node@18"]);
+    remove_duplicates(&mut requests);
+    assert_eq!(
+        requests,
+        vec![
+            pair("jq", "latest"),
+            pair("node", "latest"),
+            pair("node", "18")
+        ]
+    );
 }
@@ -0,0 +1,273 @@
+use crate::configs::index::IndexConfig;
+use crate::configs::shim::ShimConfig;
+use crate::configs::user::UserConfig;
+use crate::configs::version::VersionConfig;
+use crate::packages::Package;
+use clap::{Arg, Command, ValueEnum};
+use serde_json::Value;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// Returns a script that registers hbox completions for the given shell. Scripts delegate to
+/// the hidden `hbox __complete` command, so installed packages are always up to date.
+pub fn generate(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+        Shell::Powershell => POWERSHELL,
+    }
+}
+
+/// Returns completion candidates for the words typed after `hbox`. The last word is the one
+/// being completed, and may be empty.
+pub fn complete(command: &Command, words: &[String]) -> Vec<String> {
+    let (current, previous) = match words.split_last() {
+        Some((current, previous)) => (current.as_str(), previous),
+        None => ("", words),
+    };
+
+    let mut subcommand: Option<&Command> = None;
+    let mut positionals: Vec<&str> = Vec::new();
+    let mut pending_value: Option<&Arg> = None;
+    for word in previous {
+        if pending_value.take().is_some() {
+            continue;
+        }
+        if word.starts_with('-') {
+            pending_value = find_option(command, subcommand, word)
+                .filter(|arg| arg.get_action().takes_values() && !word.contains('='));
+            continue;
+        }
+        match subcommand {
+            None => subcommand = command.find_subcommand(word),
+            Some(_) => positionals.push(word),
+        }
+    }
+
+    let candidates = if let Some(option) = pending_value {
+        complete_option_value(option)
+    } else if current.starts_with('-') {
+        let mut options = long_options(command);
+        if let Some(subcommand) = subcommand {
+            options.extend(long_options(subcommand));
+        }
+        options
+    } else if let Some(subcommand) = subcommand {
+        complete_positional(subcommand, &positionals, current)
+    } else {
+        command
+            .get_subcommands()
+            .filter(|c| !c.is_hide_set())
+            .map(|c| c.get_name().to_string())
+            .collect()
+    };
+
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current))
+        .collect()
+}
+
+fn find_option<'a>(
+    command: &'a Command,
+    subcommand: Option<&'a Command>,
+    word: &str,
+) -> Option<&'a Arg> {
+    let word = word.split('=').next().unwrap_or(word);
+    command
+        .get_arguments()
+        .chain(subcommand.into_iter().flat_map(|c| c.get_arguments()))
+        .find(|arg| match word.strip_prefix("--") {
+            Some(long) => arg.get_long() == Some(long),
+            None => word.len() == 2 && arg.get_short() == word.chars().nth(1),
+        })
+}
+
+fn long_options(command: &Command) -> Vec<String> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .filter_map(|arg| arg.get_long().map(|long| format!("--{}", long)))
+        .collect()
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn complete_option_value(option: &Arg) -> Vec<String> {
+    match option.get_long() {
+        Some("package") => installed_packages(),
+        _ => possible_values(option),
+    }
+}
+
+fn complete_positional(subcommand: &Command, positionals: &[&str], current: &str) -> Vec<String> {
+    match (subcommand.get_name(), positionals) {
+        ("list", []) | ("remove", []) | ("use", []) | ("rebuild", []) => installed_packages(),
+        ("remove", [name]) | ("use", [name]) | ("rebuild", [name]) => installed_versions(name),
+        ("add", _) => indexed_packages(),
+        ("run", []) => match current.split_once("::") {
+            Some((name, _)) => binaries(name),
+            None => installed_packages(),
+        },
+        ("config", []) => config_keys(),
+        ("which", []) => shims(),
+        ("bundle", []) => subcommand
+            .get_subcommands()
+            .map(|c| c.get_name().to_string())
+            .collect(),
+        ("bundle", ["create", ..]) => installed_packages(),
+        _ => subcommand
+            .get_positionals()
+            .nth(positionals.len())
+            .map(possible_values)
+            .unwrap_or_default(),
+    }
+}
+
+fn installed_packages() -> Vec<String> {
+    VersionConfig::names().unwrap_or_default()
+}
+
+fn installed_versions(name: &str) -> Vec<String> {
+    match VersionConfig::load(name.to_string()) {
+        Ok(Some(package)) => package.versions,
+        _ => Vec::new(),
+    }
+}
+
+fn shims() -> Vec<String> {
+    ShimConfig::load()
+        .map(|shims| shims.into_keys().collect())
+        .unwrap_or_default()
+}
+
+fn indexed_packages() -> Vec<String> {
+    IndexConfig::names().unwrap_or_default()
+}
+
+fn binaries(name: &str) -> Vec<String> {
+    match Package::load(name) {
+        Ok(Some(package)) => package
+            .index
+            .binaries
+            .iter()
+            .flatten()
+            .map(|binary| format!("{}::{}", name, binary.name))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn config_keys() -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Ok(root) = serde_json::to_value(UserConfig::load().unwrap_or_default()) {
+        collect_config_keys(&root, "", &mut keys);
+    }
+    keys
+}
+
+fn collect_config_keys(value: &Value, prefix: &str, keys: &mut Vec<String>) {
+    if let Value::Object(map) = value {
+        for (key, value) in map {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            keys.push(path.clone());
+            collect_config_keys(value, &path, keys);
+        }
+    }
+}
+
+const BASH: &str = r#"_hbox() {
+    local line="${COMP_LINE:0:COMP_POINT}"
+    local -a words
+    read -ra words <<< "$line"
+    [[ "$line" =~ [[:space:]]$ ]] && words+=("")
+    local cur="${words[${#words[@]}-1]}"
+    local IFS=$'\n'
+    COMPREPLY=($(hbox __complete -- "${words[@]:1}" 2>/dev/null))
+    # Bash splits words on ':', so only complete what comes after the last one.
+    if [[ "$cur" == *:* && "$COMP_WORDBREAKS" == *:* ]]; then
+        local prefix="${cur%"${cur##*:}"}"
+        COMPREPLY=("${COMPREPLY[@]#"$prefix"}")
+    fi
+}
+complete -o default -F _hbox hbox
+"#;
+
+const ZSH: &str = r#"#compdef hbox
+_hbox() {
+    local -a candidates
+    candidates=(${(f)"$(hbox __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} > 0 )); then
+        compadd -- "${candidates[@]}"
+    else
+        _files
+    fi
+}
+if [ "$funcstack[1]" = "_hbox" ]; then
+    _hbox "$@"
+else
+    compdef _hbox hbox
+fi
+"#;
+
+const FISH: &str = r#"function __hbox_complete
+    set -l tokens (commandline -opc) (commandline -ct)
+    hbox __complete -- $tokens[2..-1] 2>/dev/null
+end
+complete -c hbox -f -n 'test (count (__hbox_complete)) -gt 0' -a '(__hbox_complete)'
+complete -c hbox -F -n 'test (count (__hbox_complete)) -eq 0'
+"#;
+
+const POWERSHELL: &str = r#"Register-ArgumentCompleter -Native -CommandName hbox -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object { $_.ToString() })
+    if ($wordToComplete -eq '') { $words += '' }
+    hbox __complete -- @words 2>$null | ForEach-Object {
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }
+}
+"#;
+
+#[test]
+fn verify_static_completions() {
+    let command = Command::new("hbox")
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .global(true)
+                .value_parser(["table", "json"]),
+        )
+        .subcommand(Command::new("info"))
+        .subcommand(
+            Command::new("completions").arg(Arg::new("shell").value_parser(["bash", "zsh"])),
+        )
+        .subcommand(Command::new("__complete").hide(true));
+    let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(complete(&command, &words(&[""])), ["info", "completions"]);
+    assert_eq!(complete(&command, &words(&["c"])), ["completions"]);
+    assert_eq!(complete(&command, &words(&["--o"])), ["--output"]);
+    assert_eq!(complete(&command, &words(&["-o", ""])), ["table", "json"]);
+    assert_eq!(
+        complete(&command, &words(&["-o", "json", "completions", "z"])),
+        ["zsh"]
+    );
+}
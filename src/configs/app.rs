@@ -43,7 +43,48 @@ impl AppConfig {
         self.base_dir.join("shims")
     }
 
+    pub fn shims_manifest_path(&self) -> PathBuf {
+        self.base_dir.join("shims.json")
+    }
+
+    pub fn lock_file_path(&self) -> PathBuf {
+        self.base_dir.join(".lock")
+    }
+
     pub fn logs_path(&self) -> PathBuf {
         self.base_dir.join("logs")
     }
+
+    pub fn homes_path(&self) -> PathBuf {
+        self.base_dir.join("homes")
+    }
+
+    pub fn secrets_path(&self) -> PathBuf {
+        self.base_dir.join("secrets")
+    }
+
+    pub fn history_file_path(&self) -> PathBuf {
+        self.base_dir.join("history.jsonl")
+    }
+
+    pub fn rotated_history_file_path(&self) -> PathBuf {
+        self.base_dir.join("history.1.jsonl")
+    }
+
+    pub fn cache_path(&self) -> PathBuf {
+        self.base_dir.join("cache")
+    }
+}
+
+/// Runs `test` with `HBOX_DIR` pointing to a new empty directory. As the variable is shared by
+/// the whole test process, tests using it run one at a time.
+#[cfg(test)]
+pub fn with_temp_base_dir<T>(test: impl FnOnce() -> T) -> T {
+    static BASE_DIR: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = BASE_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = crate::serialization::TempDir::create("base-dir").unwrap();
+    env::set_var("HBOX_DIR", &dir.path);
+    let result = test();
+    env::remove_var("HBOX_DIR");
+    result
 }
@@ -1,8 +1,15 @@
+use crate::configs::app::AppConfig;
+use crate::configs::project::ProjectConfig;
+use crate::host::HostUser;
 use crate::packages::Package;
+use std::env;
+use std::error::Error;
 
 pub struct Context {
     package_name: String,
     package_version: String,
+    binary_name: Option<String>,
+    image: Option<String>,
 }
 
 impl Context {
@@ -10,12 +17,94 @@ impl Context {
         Self {
             package_name: package.name.clone(),
             package_version: package.versions.current.clone(),
+            binary_name: None,
+            image: None,
         }
     }
 
-    pub fn apply(&self, mut text: String) -> String {
-        text = text.replace("${hbox_package_name}", &self.package_name);
-        text = text.replace("${hbox_package_version}", &self.package_version);
-        shellexpand::full(&text).unwrap_or_default().to_string()
+    pub fn with_binary(mut self, binary_name: Option<&str>) -> Self {
+        self.binary_name = binary_name.map(String::from);
+        self
     }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image = Some(image.to_string());
+        self
+    }
+
+    pub fn apply(&self, text: String) -> Result<String, Box<dyn Error>> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("${hbox_") {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unterminated variable in '{}'", text))?;
+            let name = &rest[start + 2..start + end];
+            result.push_str(&rest[..start]);
+            result.push_str(&self.resolve(name)?);
+            rest = &rest[start + end + 1..];
+        }
+        result.push_str(rest);
+
+        shellexpand::full(&result)
+            .map(|expanded| expanded.to_string())
+            .map_err(|e| format!("Could not expand '{}': {}", text, e).into())
+    }
+
+    fn resolve(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        let value = match name {
+            "hbox_package_name" => Some(self.package_name.clone()),
+            "hbox_package_version" => Some(self.package_version.clone()),
+            "hbox_binary_name" => Some(
+                self.binary_name
+                    .clone()
+                    .unwrap_or_else(|| self.package_name.clone()),
+            ),
+            "hbox_cwd" => env::current_dir().ok().map(|dir| dir.display().to_string()),
+            "hbox_uid" => HostUser::current().map(|user| user.uid.to_string()),
+            "hbox_gid" => HostUser::current().map(|user| user.gid.to_string()),
+            "hbox_base_dir" => Some(AppConfig::load().base_dir.display().to_string()),
+            "hbox_os" => Some(env::consts::OS.to_string()),
+            "hbox_arch" => Some(env::consts::ARCH.to_string()),
+            "hbox_project_root" => {
+                ProjectConfig::find_root().map(|root| root.display().to_string())
+            }
+            "hbox_image_digest" => self
+                .image
+                .as_ref()
+                .and_then(|image| crate::runner::image_digest(image)),
+            _ => return Err(format!("Unknown variable '${{{}}}'", name).into()),
+        };
+        value.ok_or_else(|| {
+            format!(
+                "Variable '${{{}}}' is not defined for package '{}'",
+                name, self.package_name
+            )
+            .into()
+        })
+    }
+}
+
+#[test]
+fn verify_apply_resolves_variables() {
+    use crate::configs::index::Package as PackageIndex;
+    use crate::configs::version::Package as PackageVersion;
+    let package = Package {
+        name: "node".to_string(),
+        index: PackageIndex::new("node"),
+        versions: PackageVersion::new("18"),
+    };
+    let context = Context::from(&package).with_binary(Some("npm"));
+
+    assert_eq!(
+        context
+            .apply("${hbox_package_name}:${hbox_package_version}/${hbox_binary_name}".to_string())
+            .unwrap(),
+        "node:18/npm"
+    );
+    assert!(context.apply("${hbox_unknown}".to_string()).is_err());
+    assert!(context.apply("${hbox_image_digest}".to_string()).is_err());
+    assert!(context
+        .apply("${HBOX_SURELY_UNDEFINED_VARIABLE}".to_string())
+        .is_err());
 }
@@ -1,9 +1,13 @@
 use crate::configs::app::AppConfig;
+use crate::configs::context::Context;
+use crate::logging::{mask_secret, MASK};
 use crate::serialization::parse_json;
 use log::debug;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
 use std::error::Error;
+use std::fs;
 use std::path::Path;
 
 pub struct IndexConfig {}
@@ -20,6 +24,34 @@ impl IndexConfig {
         Ok(package)
     }
 
+    /// Names of all packages available in the index and overrides directories.
+    pub fn names() -> Result<Vec<String>, Box<dyn Error>> {
+        let config = AppConfig::load();
+        let mut names = Vec::new();
+        let mut dirs = vec![config.overrides_path()];
+        if config.index_path().exists() {
+            for shard in fs::read_dir(config.index_path())? {
+                let shard = shard?.path();
+                if shard.is_dir() {
+                    dirs.push(shard);
+                }
+            }
+        }
+        for dir in dirs.iter().filter(|dir| dir.exists()) {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
     fn load_package(
         name: &str,
         index_path: &Path,
@@ -27,7 +59,7 @@ impl IndexConfig {
     ) -> Result<Package, Box<dyn Error>> {
         let shard_dir = name.chars().next().unwrap().to_string().to_lowercase();
         let index_file = index_path
-            .join(&Path::new(&shard_dir))
+            .join(Path::new(&shard_dir))
             .join(format!("{}.json", name));
         let override_file = overrides_path.join(format!("{}.json", name));
 
@@ -44,16 +76,35 @@ impl IndexConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Package {
     pub image: Image,
     pub volumes: Option<Vec<Volume>>,
     pub ports: Option<Vec<Port>>,
     pub current_directory: Option<String>,
     pub environment_variables: Option<Vec<EnvironmentVariable>>,
+    pub passthrough: Option<Vec<String>>,
+    pub env_files: Option<Vec<String>>,
     pub binaries: Option<Vec<Binary>>,
     #[serde(default)]
     pub only_shim_binaries: bool,
+    pub aliases: Option<HashMap<String, String>>,
+    pub user: Option<String>,
+    pub network: Option<String>,
+    pub cap_add: Option<Vec<String>>,
+    pub devices: Option<Vec<Device>>,
+    pub tmpfs: Option<Vec<String>>,
+    #[serde(default)]
+    pub read_only: bool,
+    pub platform: Option<String>,
+    pub shm_size: Option<String>,
+    pub extra_hosts: Option<Vec<ExtraHost>>,
+    pub security_opt: Option<Vec<String>>,
+    pub entrypoint: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
+    pub run_as_host_user: Option<bool>,
+    #[serde(default)]
+    pub passwd_entry: bool,
 }
 
 impl Package {
@@ -63,17 +114,30 @@ impl Package {
                 name: format!("docker.io/{}", name),
                 build: None,
             },
-            volumes: None,
-            ports: None,
-            current_directory: None,
-            environment_variables: None,
-            binaries: None,
-            only_shim_binaries: false,
+            ..Default::default()
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Package {
+    /// Returns a copy with every secret value replaced by a mask, safe to display.
+    pub fn masked(&self) -> Self {
+        let mut package = self.clone();
+        if let Some(environment_variables) = &mut package.environment_variables {
+            for env_var in environment_variables.iter_mut().filter(|e| e.is_secret()) {
+                env_var.value = MASK.to_string();
+            }
+        }
+        if let Some(args) = package.image.build.as_mut().and_then(|b| b.args.as_mut()) {
+            for arg in args.values_mut().filter(|a| a.is_secret()) {
+                *arg = BuildArg::Value(arg.display_value());
+            }
+        }
+        package
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Image {
     pub name: String,
     pub build: Option<Build>,
@@ -87,9 +151,63 @@ impl Image {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Build {
-    pub context: String,
-    pub dockerfile: String,
-    pub args: Option<HashMap<String, String>>,
+    pub context: Option<String>,
+    pub dockerfile: Option<String>,
+    pub inline: Option<InlineDockerfile>,
+    pub template: Option<Template>,
+    pub args: Option<HashMap<String, BuildArg>>,
+    pub target: Option<String>,
+    pub platform: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
+    pub cache_from: Option<Vec<String>>,
+    pub secrets: Option<Vec<BuildSecret>>,
+    pub ssh: Option<Vec<String>>,
+}
+
+/// Secret file mounted into `RUN --mount=type=secret,id=<id>` instructions of a build. Only
+/// its path is passed to the engine, so its content never shows up in logs or in the image.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildSecret {
+    pub id: String,
+    pub src: String,
+}
+
+/// Dockerfile defined in the package itself, as a single string or a list of lines.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum InlineDockerfile {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl InlineDockerfile {
+    pub fn text(&self) -> String {
+        match self {
+            InlineDockerfile::Text(text) => text.clone(),
+            InlineDockerfile::Lines(lines) => lines.join("\n"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Template {
+    pub name: TemplateName,
+    pub base: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateName {
+    /// Installs packages with `apk`.
+    Alpine,
+    /// Installs packages with `apt-get`.
+    Debian,
+    /// Installs packages with `pip`.
+    Python,
+    /// Installs packages with `npm`.
+    Node,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -104,6 +222,19 @@ pub struct Port {
     pub container: u16,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub host: String,
+    pub container: Option<String>,
+    pub permissions: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtraHost {
+    pub host: String,
+    pub ip: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Binary {
     pub name: String,
@@ -116,5 +247,110 @@ pub struct Binary {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EnvironmentVariable {
     pub name: String,
+    #[serde(default)]
     pub value: String,
+    #[serde(default)]
+    pub secret: bool,
+    pub secret_ref: Option<SecretRef>,
+}
+
+impl EnvironmentVariable {
+    pub fn is_secret(&self) -> bool {
+        self.secret || self.secret_ref.is_some()
+    }
+
+    pub fn resolve(&self, context: &Context) -> Result<String, Box<dyn Error>> {
+        resolve_value(&self.value, self.secret, &self.secret_ref, context)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BuildArg {
+    Value(String),
+    Detailed {
+        #[serde(default)]
+        value: String,
+        #[serde(default)]
+        secret: bool,
+        secret_ref: Option<SecretRef>,
+    },
+}
+
+impl BuildArg {
+    pub fn is_secret(&self) -> bool {
+        match self {
+            BuildArg::Value(_) => false,
+            BuildArg::Detailed {
+                secret, secret_ref, ..
+            } => *secret || secret_ref.is_some(),
+        }
+    }
+
+    pub fn resolve(&self, context: &Context) -> Result<String, Box<dyn Error>> {
+        match self {
+            BuildArg::Value(value) => context.apply(value.clone()),
+            BuildArg::Detailed {
+                value,
+                secret,
+                secret_ref,
+            } => resolve_value(value, *secret, secret_ref, context),
+        }
+    }
+
+    /// Returns the configured value, masking it when it is a secret.
+    pub fn display_value(&self) -> String {
+        match self {
+            BuildArg::Value(value) => value.clone(),
+            BuildArg::Detailed { .. } if self.is_secret() => MASK.to_string(),
+            BuildArg::Detailed { value, .. } => value.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretRef {
+    /// Reads the secret from a file on the host.
+    File(String),
+    /// Reads the secret from a host environment variable.
+    Env(String),
+    /// Reads the secret from `$HBOX_DIR/secrets/<key>`.
+    Store(String),
+}
+
+impl SecretRef {
+    pub fn resolve(&self, context: &Context) -> Result<String, Box<dyn Error>> {
+        let value = match self {
+            SecretRef::File(path) => {
+                let path = context.apply(path.clone())?;
+                fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read secret file '{}': {}", path, e))?
+            }
+            SecretRef::Env(name) => env::var(name)
+                .map_err(|_| format!("Secret environment variable '{}' is not set", name))?,
+            SecretRef::Store(key) => {
+                let path = AppConfig::load().secrets_path().join(key);
+                fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read secret '{}' from store: {}", key, e))?
+            }
+        };
+        Ok(value.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn resolve_value(
+    value: &str,
+    secret: bool,
+    secret_ref: &Option<SecretRef>,
+    context: &Context,
+) -> Result<String, Box<dyn Error>> {
+    let resolved = match secret_ref {
+        Some(secret_ref) => secret_ref.resolve(context)?,
+        None => context.apply(value.to_string())?,
+    };
+    if secret || secret_ref.is_some() {
+        mask_secret(&resolved);
+    }
+    Ok(resolved)
 }
@@ -1,5 +1,7 @@
 pub mod app;
 pub mod context;
 pub mod index;
+pub mod project;
+pub mod shim;
 pub mod user;
 pub mod version;
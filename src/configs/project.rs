@@ -0,0 +1,40 @@
+use crate::serialization::parse_json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+
+pub const PROJECT_FILE_NAME: &str = ".hbox.json";
+
+pub struct ProjectConfig {}
+
+impl ProjectConfig {
+    /// Traverses the current directory and its parents until a project file is found.
+    pub fn find_root() -> Option<PathBuf> {
+        let current_dir = env::current_dir().ok()?;
+        current_dir
+            .ancestors()
+            .find(|dir| dir.join(PROJECT_FILE_NAME).is_file())
+            .map(|dir| dir.to_path_buf())
+    }
+
+    pub fn load() -> Result<Option<(PathBuf, Project)>, Box<dyn Error>> {
+        match Self::find_root() {
+            Some(root) => {
+                let project_file = root.join(PROJECT_FILE_NAME);
+                let project = parse_json(&project_file)
+                    .map_err(|e| format!("Invalid project file {:?}: {}", project_file, e))?;
+                Ok(Some((project_file, project)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Project {
+    /// Versions pinned for this project, by package name.
+    #[serde(default)]
+    pub packages: HashMap<String, String>,
+}
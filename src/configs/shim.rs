@@ -0,0 +1,65 @@
+use crate::configs::app::AppConfig;
+use crate::serialization::{parse_json, save_json};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Manifest of the shims created by hbox, recording which package and binary owns each one.
+pub struct ShimConfig {}
+
+impl ShimConfig {
+    pub fn load() -> Result<BTreeMap<String, Shim>, Box<dyn Error>> {
+        let config = AppConfig::load();
+        let manifest_file = config.shims_manifest_path();
+        if manifest_file.exists() {
+            Ok(parse_json(&manifest_file)?)
+        } else {
+            Ok(BTreeMap::new())
+        }
+    }
+
+    pub fn find(name: &str) -> Result<Option<Shim>, Box<dyn Error>> {
+        Ok(Self::load()?.remove(name))
+    }
+
+    pub fn upsert(name: &str, shim: Shim) -> Result<(), Box<dyn Error>> {
+        debug!("Registering shim '{}' for '{}'", name, shim.command());
+        let mut shims = Self::load()?;
+        shims.insert(name.to_string(), shim);
+        Self::save(&shims)
+    }
+
+    pub fn remove(name: &str) -> Result<(), Box<dyn Error>> {
+        let mut shims = Self::load()?;
+        if shims.remove(name).is_some() {
+            debug!("Unregistering shim '{}'", name);
+            Self::save(&shims)?;
+        }
+        Ok(())
+    }
+
+    fn save(shims: &BTreeMap<String, Shim>) -> Result<(), Box<dyn Error>> {
+        let config = AppConfig::load();
+        let manifest_file = config.shims_manifest_path();
+        debug!("Saving file {:?}", &manifest_file);
+        save_json(shims, &manifest_file)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shim {
+    pub package: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary: Option<String>,
+}
+
+impl Shim {
+    /// Command run by this shim, either the package name or `package::binary`.
+    pub fn command(&self) -> String {
+        match &self.binary {
+            Some(binary) => format!("{}::{}", self.package, binary),
+            None => self.package.clone(),
+        }
+    }
+}
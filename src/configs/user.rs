@@ -57,22 +57,14 @@ impl UserConfig {
         Ok(())
     }
 
-    pub fn read_config_value(path: &str) -> Result<(), Box<dyn Error>> {
+    pub fn read_config_value(path: &str) -> Result<Value, Box<dyn Error>> {
         let mut root: Value = serde_json::to_value(Self::load()?)?;
         let (current, last_part) = Self::traverse_path(&mut root, path)?;
 
         match current.get(&last_part) {
-            Some(value) => {
-                if value.is_object() {
-                    println!("{}", serde_json::to_string_pretty(&value)?);
-                } else {
-                    println!("{}", value);
-                }
-            }
-            None => return Err(format!("Invalid configuration key: {}", path).into()),
+            Some(value) => Ok(value.clone()),
+            None => Err(format!("Invalid configuration key: {}", path).into()),
         }
-
-        Ok(())
     }
 
     fn traverse_path<'a>(
@@ -100,23 +92,26 @@ impl UserConfig {
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Root {
     pub engine: Engine,
+    #[serde(default)]
+    pub run_as_host_user: bool,
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub images: Images,
+    #[serde(default)]
+    pub shims: Shims,
     pub logs: Logs,
     pub experimental: Experimental,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Engine {
+    #[default]
     Docker,
     Podman,
 }
 
-impl Default for Engine {
-    fn default() -> Self {
-        Self::Docker
-    }
-}
-
 impl Engine {
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -138,12 +133,98 @@ impl FromStr for Engine {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Images {
+    #[serde(default = "default_auto_pull")]
+    pub auto_pull: bool,
+    #[serde(default = "default_rebuild_on_change")]
+    pub rebuild_on_change: bool,
+    #[serde(default = "default_remove_on_rollback")]
+    pub remove_on_rollback: bool,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self {
+            auto_pull: default_auto_pull(),
+            rebuild_on_change: default_rebuild_on_change(),
+            remove_on_rollback: default_remove_on_rollback(),
+        }
+    }
+}
+
+fn default_auto_pull() -> bool {
+    true
+}
+
+fn default_rebuild_on_change() -> bool {
+    true
+}
+
+fn default_remove_on_rollback() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Shims {
+    #[serde(default)]
+    pub mode: ShimMode,
+    #[serde(default)]
+    pub conflicts: ConflictPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShimMode {
+    /// Shell scripts (batch files on Windows) calling `hbox run`.
+    #[default]
+    Script,
+    /// Symbolic links to the hbox binary, which dispatches based on the program name.
+    Symlink,
+    /// Hard links to the hbox binary, which dispatches based on the program name.
+    Hardlink,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Fails to add the package.
+    Fail,
+    /// Creates the shim anyway, logging a warning. Shims of other packages are skipped instead.
+    #[default]
+    Warn,
+    /// Doesn't create the shim.
+    Skip,
+    /// Creates the shim under its alias instead.
+    Alias,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Logs {
     #[serde(default)]
     pub enabled: bool,
     pub level: Level,
     pub strategy: Strategy,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_size_mb() -> u64 {
+    10
+}
+
+fn default_max_age_days() -> u64 {
+    7
+}
+
+fn default_max_files() -> usize {
+    5
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -163,6 +244,14 @@ pub enum Strategy {
     Append,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Experimental {
     #[serde(default)]
@@ -177,6 +266,10 @@ impl Default for Logs {
             enabled: false,
             level: Level::Info,
             strategy: Strategy::Append,
+            format: Format::default(),
+            max_size_mb: default_max_size_mb(),
+            max_age_days: default_max_age_days(),
+            max_files: default_max_files(),
         }
     }
 }
@@ -219,3 +312,42 @@ impl FromStr for Strategy {
         }
     }
 }
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Format, Self::Err> {
+        match input {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ShimMode {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ShimMode, Self::Err> {
+        match input {
+            "script" => Ok(ShimMode::Script),
+            "symlink" => Ok(ShimMode::Symlink),
+            "hardlink" => Ok(ShimMode::Hardlink),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ConflictPolicy, Self::Err> {
+        match input {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "warn" => Ok(ConflictPolicy::Warn),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "alias" => Ok(ConflictPolicy::Alias),
+            _ => Err(()),
+        }
+    }
+}
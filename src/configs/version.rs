@@ -1,9 +1,11 @@
 use crate::configs::app::AppConfig;
+use crate::dry_run::{is_dry_run, plan};
 use crate::serialization::{parse_json, save_json};
 use log::debug;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::fs::remove_file;
+use std::fs::{create_dir_all, read_dir, remove_file};
 
 pub struct VersionConfig {}
 
@@ -18,12 +20,46 @@ impl VersionConfig {
         }
     }
 
+    pub fn names() -> Result<Vec<String>, Box<dyn Error>> {
+        let config = AppConfig::load();
+        if !config.versions_path().exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in read_dir(config.versions_path())? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
     pub fn upsert(name: &str, package: crate::packages::Package) -> Result<(), Box<dyn Error>> {
         debug!("Adding/Updating package '{}'", name);
+        Self::save(name, &package.versions)
+    }
+
+    pub fn save(name: &str, versions: &Package) -> Result<(), Box<dyn Error>> {
         let config = AppConfig::load();
         let version_file = config.versions_path().join(format!("{}.json", name));
+        if !config.versions_path().exists() && !is_dry_run() {
+            create_dir_all(config.versions_path())?;
+        }
         debug!("Saving file {:?}", &version_file);
-        save_json(&package.versions, &version_file)?;
+        save_json(versions, &version_file)?;
+        Ok(())
+    }
+
+    /// Records the build hash of a version, keeping the rest of the versions file as it is.
+    pub fn save_build_hash(name: &str, version: &str, hash: String) -> Result<(), Box<dyn Error>> {
+        if let Some(mut versions) = Self::load(name.to_string())? {
+            versions.builds.insert(version.to_string(), hash);
+            Self::save(name, &versions)?;
+        }
         Ok(())
     }
 
@@ -33,7 +69,11 @@ impl VersionConfig {
         let version_file = config.versions_path().join(format!("{}.json", name));
         if version_file.exists() {
             debug!("Removing file {:?}", &version_file);
-            remove_file(&version_file)?
+            if is_dry_run() {
+                plan(format!("remove {}", version_file.display()));
+            } else {
+                remove_file(&version_file)?
+            }
         }
         Ok(())
     }
@@ -43,6 +83,9 @@ impl VersionConfig {
 pub struct Package {
     pub versions: Vec<String>,
     pub current: String,
+    /// Hash of what each locally built version was built from, used to detect changes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub builds: BTreeMap<String, String>,
 }
 
 impl Package {
@@ -50,6 +93,7 @@ impl Package {
         Package {
             versions: vec![String::from(version)],
             current: String::from(version),
+            builds: BTreeMap::new(),
         }
     }
 }
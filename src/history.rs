@@ -0,0 +1,211 @@
+use crate::configs::app::AppConfig;
+use crate::lock::StateLock;
+use crate::packages::Package;
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Size above which the history is rotated, replacing the previously rotated entries.
+const MAX_HISTORY_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Invocation {
+    pub timestamp: DateTime<Local>,
+    pub package: String,
+    pub binary: Option<String>,
+    pub version: String,
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    pub cwd: Option<PathBuf>,
+}
+
+pub struct History {}
+
+impl History {
+    pub fn record(invocation: &Invocation) {
+        if let Err(e) = Self::append(invocation) {
+            warn!("Could not record invocation in history: {}", e);
+        }
+    }
+
+    pub fn load() -> Result<Vec<Invocation>, Box<dyn Error>> {
+        let config = AppConfig::load();
+        let mut content = String::new();
+        for path in [
+            config.rotated_history_file_path(),
+            config.history_file_path(),
+        ] {
+            if path.exists() {
+                content.push_str(&fs::read_to_string(&path)?);
+            }
+        }
+        Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(invocation) => Some(invocation),
+                Err(e) => {
+                    debug!("Skipping invalid history entry: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    fn append(invocation: &Invocation) -> Result<(), Box<dyn Error>> {
+        let config = AppConfig::load();
+        let path = config.history_file_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(invocation)?)?;
+        if file.metadata()?.len() > MAX_HISTORY_SIZE {
+            let _lock = StateLock::acquire()?;
+            rotate(&path, &config.rotated_history_file_path())?;
+        }
+        Ok(())
+    }
+}
+
+/// Moves the history to `rotated_path` if it's still too big, as another process may have
+/// rotated it already. Runs are appended without locking, and moving the file keeps the entries
+/// appended meanwhile, either in the moved file or in a new one.
+fn rotate(path: &Path, rotated_path: &Path) -> Result<(), Box<dyn Error>> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() > MAX_HISTORY_SIZE) {
+        debug!("Rotating history {:?}", path);
+        fs::rename(path, rotated_path)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub most_used: Vec<Usage>,
+    pub unused_versions: Vec<UnusedVersion>,
+    pub slowest_startups: Vec<Startup>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Usage {
+    pub package: String,
+    pub binary: Option<String>,
+    pub runs: usize,
+    pub failures: usize,
+    pub last_used: DateTime<Local>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnusedVersion {
+    pub package: String,
+    pub version: String,
+    pub current: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Startup {
+    pub package: String,
+    pub version: String,
+    /// Fastest run observed, which is the lower bound of the time taken to start the container.
+    pub startup_ms: u64,
+    pub runs: usize,
+}
+
+impl Stats {
+    pub fn from(invocations: &[Invocation], packages: &[Package], limit: usize) -> Self {
+        let mut usages: HashMap<(String, Option<String>), Usage> = HashMap::new();
+        let mut startups: HashMap<(String, String), Startup> = HashMap::new();
+        for invocation in invocations {
+            let usage = usages
+                .entry((invocation.package.clone(), invocation.binary.clone()))
+                .or_insert_with(|| Usage {
+                    package: invocation.package.clone(),
+                    binary: invocation.binary.clone(),
+                    runs: 0,
+                    failures: 0,
+                    last_used: invocation.timestamp,
+                });
+            usage.runs += 1;
+            if invocation.exit_code != 0 {
+                usage.failures += 1;
+            }
+            usage.last_used = usage.last_used.max(invocation.timestamp);
+
+            let startup = startups
+                .entry((invocation.package.clone(), invocation.version.clone()))
+                .or_insert_with(|| Startup {
+                    package: invocation.package.clone(),
+                    version: invocation.version.clone(),
+                    startup_ms: invocation.duration_ms,
+                    runs: 0,
+                });
+            startup.runs += 1;
+            startup.startup_ms = startup.startup_ms.min(invocation.duration_ms);
+        }
+
+        let mut most_used: Vec<Usage> = usages.into_values().collect();
+        most_used.sort_by(|a, b| {
+            b.runs
+                .cmp(&a.runs)
+                .then_with(|| a.package.cmp(&b.package))
+                .then_with(|| a.binary.cmp(&b.binary))
+        });
+        most_used.truncate(limit);
+
+        let mut unused_versions = Vec::new();
+        for package in packages {
+            for version in &package.versions.versions {
+                if !startups.contains_key(&(package.name.clone(), version.clone())) {
+                    unused_versions.push(UnusedVersion {
+                        package: package.name.clone(),
+                        version: version.clone(),
+                        current: version == &package.versions.current,
+                    });
+                }
+            }
+        }
+        unused_versions.sort_by(|a, b| (&a.package, &a.version).cmp(&(&b.package, &b.version)));
+
+        let mut slowest_startups: Vec<Startup> = startups.into_values().collect();
+        slowest_startups.sort_by(|a, b| {
+            b.startup_ms
+                .cmp(&a.startup_ms)
+                .then_with(|| a.package.cmp(&b.package))
+        });
+        slowest_startups.truncate(limit);
+
+        Self {
+            most_used,
+            unused_versions,
+            slowest_startups,
+        }
+    }
+}
+
+#[test]
+fn verify_history_rotation() {
+    let dir = crate::serialization::TempDir::create("history").unwrap();
+    let path = dir.path.join("history.jsonl");
+    let rotated_path = dir.path.join("history.1.jsonl");
+
+    fs::write(&path, "small\n").unwrap();
+    rotate(&path, &rotated_path).unwrap();
+    assert!(path.exists() && !rotated_path.exists());
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.set_len(MAX_HISTORY_SIZE + 1).unwrap();
+    rotate(&path, &rotated_path).unwrap();
+    assert!(!path.exists() && rotated_path.exists());
+    // An entry appended to the moved file is kept in the rotated history.
+    writeln!(file, "late").unwrap();
+    let rotated = fs::read_to_string(&rotated_path).unwrap();
+    assert!(rotated.ends_with("late\n"));
+    // A second rotation finds nothing left to move.
+    rotate(&path, &rotated_path).unwrap();
+    assert!(rotated_path.exists());
+}
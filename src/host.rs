@@ -0,0 +1,23 @@
+#[derive(Debug, Clone)]
+pub struct HostUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub name: String,
+}
+
+impl HostUser {
+    #[cfg(unix)]
+    pub fn current() -> Option<Self> {
+        // SAFETY: getuid and getgid are always successful and have no side effects.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let name = std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .unwrap_or_else(|_| "hbox".to_string());
+        Some(Self { uid, gid, name })
+    }
+
+    #[cfg(not(unix))]
+    pub fn current() -> Option<Self> {
+        None
+    }
+}
@@ -1,8 +1,20 @@
+pub mod activation;
+pub mod build_context;
+pub mod bundle;
 pub mod cli;
 pub mod commands;
+pub mod completions;
 pub mod configs;
+pub mod dry_run;
+pub mod history;
+pub mod host;
+pub mod lock;
 pub mod logging;
+pub mod output;
 pub mod packages;
+pub mod progress;
 pub mod runner;
 pub mod serialization;
 pub mod shims;
+pub mod templates;
+pub mod transaction;
@@ -0,0 +1,70 @@
+use crate::configs::app::AppConfig;
+use log::{debug, info};
+use std::error::Error;
+use std::fs::{self, File};
+
+/// Advisory lock on `HBOX_DIR`, held by commands that change hbox state so concurrent hbox
+/// processes sharing the same directory don't overwrite each other's changes. It's released
+/// when dropped or when the process exits.
+pub struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    /// Takes the lock, waiting for other hbox processes holding it to finish.
+    pub fn acquire() -> Result<Self, Box<dyn Error>> {
+        let config = AppConfig::load();
+        fs::create_dir_all(&config.base_dir)?;
+        let lock_file_path = config.lock_file_path();
+        debug!("Locking {:?}", &lock_file_path);
+        let file = lock(&lock_file_path)?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn lock(lock_file_path: &std::path::Path) -> Result<File, Box<dyn Error>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_file_path)?;
+    // SAFETY: flock only operates on the file descriptor, which is valid while `file` lives.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        info!("Waiting for another hbox process to finish");
+        // SAFETY: same as above.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn lock(lock_file_path: &std::path::Path) -> Result<File, Box<dyn Error>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    let mut waiting = false;
+    loop {
+        // Without sharing, opening the file fails while another process has it open.
+        match fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .share_mode(0)
+            .open(lock_file_path)
+        {
+            Ok(file) => return Ok(file),
+            Err(e) if e.raw_os_error() == Some(32) => {
+                if !waiting {
+                    info!("Waiting for another hbox process to finish");
+                    waiting = true;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
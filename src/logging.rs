@@ -1,25 +1,116 @@
 use crate::configs::app::AppConfig;
 use crate::configs::user::Strategy::{Append, Truncate};
-use crate::configs::user::UserConfig;
+use crate::configs::user::{Format, Logs, UserConfig};
 use chrono::Local;
+use env_filter::{self as filter, Filter};
 use log::{Level, LevelFilter, Log, Metadata, Record};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde_json::json;
+use std::env;
 use std::error::Error;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
-use std::path::MAIN_SEPARATOR;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-pub fn setup_logger() -> Result<(), Box<dyn Error>> {
-    let logs_config = UserConfig::load().unwrap_or_default().logs;
+pub const MASK: &str = "***";
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Registers a secret value so it's replaced by `***` in every log record and printed message.
+pub fn mask_secret(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|secret| secret == value) {
+        secrets.push(value.to_string());
+        // Longer secrets go first, so a secret containing another one is fully masked.
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    }
+}
+
+/// Replaces the registered secrets found in a text.
+pub fn mask(text: &str) -> String {
+    let secrets = SECRETS.lock().unwrap();
+    secrets
+        .iter()
+        .fold(text.to_string(), |text, secret| text.replace(secret, MASK))
+}
+
+/// Replaces the values of the `-e`/`--env` options of a command line, so variables given on the
+/// command line don't end up in the logs.
+pub fn redact_env_args(args: &[String]) -> Vec<String> {
+    let redact = |env_var: &str| match env_var.split_once('=') {
+        Some((name, _)) => format!("{}={}", name, MASK),
+        None => env_var.to_string(),
+    };
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut is_value = false;
+    for arg in args {
+        if is_value {
+            redacted.push(redact(arg));
+            is_value = false;
+        } else if arg == "-e" || arg == "--env" {
+            redacted.push(arg.clone());
+            is_value = true;
+        } else if let Some(env_var) = arg.strip_prefix("--env=") {
+            redacted.push(format!("--env={}", redact(env_var)));
+        } else if let Some(env_var) = arg.strip_prefix("-e").filter(|_| !arg.starts_with("--")) {
+            redacted.push(format!("-e{}", redact(env_var)));
+        } else {
+            redacted.push(arg.clone());
+        }
+    }
+    redacted
+}
+
+/// Log target used for the captured output of commands, which is already printed as is.
+pub const COMMAND_OUTPUT_TARGET: &str = "hbox::command_output";
+
+/// Environment variable with `RUST_LOG`-style filters, like `hbox::runner=debug,warn`.
+pub const LOG_FILTER_ENV: &str = "HBOX_LOG";
+
+static INVOCATION: Mutex<Option<Invocation>> = Mutex::new(None);
+
+/// Information about the current hbox execution, added to structured log records.
+struct Invocation {
+    id: String,
+    started_at: Instant,
+    package: Option<String>,
+    version: Option<String>,
+}
 
+/// Sets the package and version the current invocation is working with.
+pub fn set_log_package(package: &str, version: &str) {
+    if let Some(invocation) = INVOCATION.lock().unwrap().as_mut() {
+        invocation.package = Some(package.to_string());
+        invocation.version = Some(version.to_string());
+    }
+}
+
+pub fn log_file_path(logs_config: &Logs) -> PathBuf {
     let config = AppConfig::load();
-    let log_file_path = config.logs_path().join("logs.txt");
+    match logs_config.format {
+        Format::Text => config.logs_path().join("logs.txt"),
+        Format::Json => config.logs_path().join("logs.jsonl"),
+    }
+}
+
+pub fn setup_logger(verbosity: u8, quiet: bool) -> Result<(), Box<dyn Error>> {
+    let logs_config = UserConfig::load().unwrap_or_default().logs;
+    let log_file_path = log_file_path(&logs_config);
 
     // Ensure the logs directory exists
     if let Some(parent) = log_file_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
 
+    if logs_config.enabled && logs_config.strategy == Append {
+        rotate(&log_file_path, &logs_config)?;
+    }
+
     // Open or create the log file
     let file = OpenOptions::new()
         .create(true)
@@ -30,66 +121,140 @@ pub fn setup_logger() -> Result<(), Box<dyn Error>> {
 
     let file = Arc::new(Mutex::new(file));
 
+    let console_level = if quiet {
+        LevelFilter::Error
+    } else {
+        match verbosity {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+    let file_filter = build_filter(logs_config.level.to_level_filter());
+    let console_filter = build_filter(console_level);
+
+    let max_level = if logs_config.enabled {
+        file_filter.filter().max(console_filter.filter())
+    } else {
+        console_filter.filter()
+    };
+
+    let id: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    *INVOCATION.lock().unwrap() = Some(Invocation {
+        id,
+        started_at: Instant::now(),
+        package: None,
+        version: None,
+    });
+
     // Set the logger
     log::set_boxed_logger(Box::new(Logger {
         file: file.clone(),
-        level: logs_config.level.to_level_filter(),
+        file_filter,
+        console_filter,
         enabled: logs_config.enabled,
+        format: logs_config.format,
     }))?;
-    log::set_max_level(logs_config.level.to_level_filter());
+    log::set_max_level(max_level);
 
     Ok(())
 }
 
+/// Rotates the log file when it's too big or too old, keeping at most `max_files` old files
+/// named `<name>.1.<ext>` (newest) to `<name>.<max_files>.<ext>` (oldest).
+fn rotate(log_file_path: &Path, logs_config: &Logs) -> Result<(), Box<dyn Error>> {
+    let Ok(metadata) = fs::metadata(log_file_path) else {
+        return Ok(());
+    };
+
+    let too_big =
+        logs_config.max_size_mb > 0 && metadata.len() > logs_config.max_size_mb * 1024 * 1024;
+    let too_old = logs_config.max_age_days > 0
+        && metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .ok()
+            .and_then(|created| SystemTime::now().duration_since(created).ok())
+            .is_some_and(|age| age > Duration::from_secs(logs_config.max_age_days * 24 * 60 * 60));
+    if !too_big && !too_old {
+        return Ok(());
+    }
+
+    if logs_config.max_files == 0 {
+        fs::remove_file(log_file_path)?;
+        return Ok(());
+    }
+    let oldest = rotated_log_file_path(log_file_path, logs_config.max_files);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for index in (1..logs_config.max_files).rev() {
+        let path = rotated_log_file_path(log_file_path, index);
+        if path.exists() {
+            fs::rename(&path, rotated_log_file_path(log_file_path, index + 1))?;
+        }
+    }
+    fs::rename(log_file_path, rotated_log_file_path(log_file_path, 1))?;
+    Ok(())
+}
+
+pub fn rotated_log_file_path(log_file_path: &Path, index: usize) -> PathBuf {
+    let stem = log_file_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("logs");
+    let extension = log_file_path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("txt");
+    log_file_path.with_file_name(format!("{}.{}.{}", stem, index, extension))
+}
+
+fn build_filter(default_level: LevelFilter) -> Filter {
+    let mut builder = filter::Builder::new();
+    builder.filter_level(default_level);
+    if let Ok(filters) = env::var(LOG_FILTER_ENV) {
+        builder.parse(&filters);
+    }
+    builder.build()
+}
+
 struct Logger {
     file: Arc<Mutex<File>>,
-    level: LevelFilter,
+    file_filter: Filter,
+    console_filter: Filter,
     enabled: bool,
+    format: Format,
 }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        (self.enabled && self.file_filter.enabled(metadata))
+            || self.console_filter.enabled(metadata)
     }
 
     fn log(&self, record: &Record) {
-        if !self.enabled(record.metadata()) {
-            return;
-        }
+        let message = mask(&record.args().to_string());
 
-        let now = Local::now();
-        let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f");
-
-        let file_info = match (record.file(), record.line()) {
-            (Some(file), Some(line)) => format!("{}:{}", strip_src_prefix(file).to_string(), line),
-            _ => String::from("unknown"),
-        };
-
-        // Define fixed widths
-        let log_line = format!(
-            "[{:<width$} {:<level_width$} {:<file_info_width$}] {}",
-            timestamp,
-            record.level(),
-            file_info,
-            record.args(),
-            width = 23,
-            level_width = 5,
-            file_info_width = 23
-        );
-
-        if self.enabled {
+        if self.enabled && self.file_filter.matches(record) {
+            let log_line = match self.format {
+                Format::Text => format_text(record, &message),
+                Format::Json => format_json(record, &message),
+            };
             let mut file = self.file.lock().unwrap();
             writeln!(file, "{}", log_line).unwrap();
         }
 
-        match record.level() {
-            Level::Info | Level::Warn => {
-                println!("{}", record.args());
-            }
-            Level::Error => {
-                eprintln!("{}", record.args());
+        if record.target() != COMMAND_OUTPUT_TARGET && self.console_filter.matches(record) {
+            match record.level() {
+                Level::Error | Level::Warn => eprintln!("{}", message),
+                level => eprintln!("[{:<5} {}] {}", level, record.target(), message),
             }
-            _ => (),
         }
     }
 
@@ -99,7 +264,131 @@ impl Log for Logger {
     }
 }
 
+/// Checks if a line from the log file was written while working with the given package.
+pub fn log_line_has_package(line: &str, format: &Format, package: &str) -> bool {
+    match format {
+        Format::Text => line.contains(&format!("] [{}@", package)),
+        Format::Json => serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|record| record.get("package").cloned())
+            .is_some_and(|value| value == package),
+    }
+}
+
+fn format_text(record: &Record, message: &str) -> String {
+    let now = Local::now();
+    let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f");
+
+    let file_info = match (record.file(), record.line()) {
+        (Some(file), Some(line)) => format!("{}:{}", strip_src_prefix(file), line),
+        _ => String::from("unknown"),
+    };
+
+    let package = INVOCATION
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(
+            |invocation| match (&invocation.package, &invocation.version) {
+                (Some(package), Some(version)) => Some(format!("[{}@{}] ", package, version)),
+                _ => None,
+            },
+        )
+        .unwrap_or_default();
+
+    // Define fixed widths
+    format!(
+        "[{:<width$} {:<level_width$} {:<file_info_width$}] {}{}",
+        timestamp,
+        record.level(),
+        file_info,
+        package,
+        message,
+        width = 23,
+        level_width = 5,
+        file_info_width = 23
+    )
+}
+
+fn format_json(record: &Record, message: &str) -> String {
+    let invocation = INVOCATION.lock().unwrap();
+    let invocation = invocation.as_ref();
+    json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "file": record.file().map(strip_src_prefix),
+        "line": record.line(),
+        "message": message,
+        "invocation_id": invocation.map(|i| i.id.clone()),
+        "package": invocation.and_then(|i| i.package.clone()),
+        "version": invocation.and_then(|i| i.version.clone()),
+        "duration_ms": invocation.map(|i| i.started_at.elapsed().as_millis() as u64),
+    })
+    .to_string()
+}
+
 fn strip_src_prefix(file_path: &str) -> &str {
     let prefix = format!("src{}", MAIN_SEPARATOR);
     file_path.strip_prefix(&prefix).unwrap_or(file_path)
 }
+
+#[test]
+fn verify_secrets_are_masked() {
+    mask_secret("s3cr3t-value");
+    mask_secret("");
+    mask_secret("pw1");
+    assert_eq!(
+        mask("docker run -e TOKEN=s3cr3t-value busybox"),
+        "docker run -e TOKEN=*** busybox"
+    );
+    assert_eq!(mask("nothing to hide"), "nothing to hide");
+    assert_eq!(mask("xpw1x"), "x***x");
+
+    let args: Vec<String> = ["run", "-e", "A=1", "--env", "B", "--env=C=2", "-eD=3", "jq"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    assert_eq!(
+        redact_env_args(&args),
+        [
+            "run",
+            "-e",
+            "A=***",
+            "--env",
+            "B",
+            "--env=C=***",
+            "-eD=***",
+            "jq"
+        ]
+    );
+}
+
+#[test]
+fn verify_log_rotation() {
+    let dir = env::temp_dir().join(format!("hbox-rotation-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log_file = dir.join("logs.txt");
+    let logs_config = Logs {
+        max_size_mb: 1,
+        max_files: 2,
+        ..Logs::default()
+    };
+
+    for generation in 0..3 {
+        fs::write(&log_file, vec![b'0' + generation; 1024 * 1024 + 1]).unwrap();
+        rotate(&log_file, &logs_config).unwrap();
+    }
+
+    assert!(!log_file.exists());
+    assert_eq!(
+        fs::read(rotated_log_file_path(&log_file, 1)).unwrap()[0],
+        b'2'
+    );
+    assert_eq!(
+        fs::read(rotated_log_file_path(&log_file, 2)).unwrap()[0],
+        b'1'
+    );
+    assert!(!rotated_log_file_path(&log_file, 3).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,61 @@
+use crate::logging::mask;
+use crate::serialization::to_yaml;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a command result to stdout, unless quiet mode is on.
+#[macro_export]
+macro_rules! report {
+    ($($arg:tt)*) => {
+        $crate::output::print(format!($($arg)*))
+    };
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn print(message: impl Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        println!("{}", mask(&message.to_string()));
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Table,
+    /// JSON output, meant to be consumed by other tools
+    Json,
+    /// YAML output, meant to be consumed by other tools
+    Yaml,
+}
+
+impl OutputFormat {
+    pub fn is_structured(&self) -> bool {
+        *self != Self::Table
+    }
+}
+
+/// Prints data as JSON or YAML to stdout. It's printed even in quiet mode, since it was requested explicitly.
+pub fn print_structured<T: Serialize>(
+    data: &T,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(data)?),
+        OutputFormat::Yaml => print!("{}", to_yaml(data)?),
+        OutputFormat::Table => return Err("Table output is not structured".into()),
+    }
+    Ok(())
+}
@@ -1,9 +1,20 @@
 use crate::configs::app::AppConfig;
+use crate::configs::context::Context;
 use crate::configs::index::Package as PackageIndex;
+use crate::configs::project::ProjectConfig;
+use crate::configs::shim::Shim;
 use crate::configs::version::Package as PackageVersion;
-use log::{debug, info};
+use crate::logging::MASK;
+use crate::report;
+use crate::runner::image_reference;
+use log::debug;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
 pub struct Package {
@@ -12,6 +23,39 @@ pub struct Package {
     pub versions: PackageVersion,
 }
 
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionSource {
+    /// The current version set via `hbox add --set-default` or `hbox use`.
+    Default,
+    /// A version pinned in a project file.
+    Project(PathBuf),
+    /// A version pinned via an environment variable.
+    Env(String),
+}
+
+impl fmt::Display for VersionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSource::Default => write!(f, "default"),
+            VersionSource::Project(project_file) => write!(f, "project {:?}", project_file),
+            VersionSource::Env(variable) => write!(f, "env {}", variable),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PackageSummary {
+    pub name: String,
+    pub current: String,
+    pub versions: Vec<String>,
+    pub image: String,
+    pub binaries: Vec<String>,
+    pub shims: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<PackageIndex>,
+}
+
 // Public API
 impl Package {
     pub fn new(name: &str, versions_package: PackageVersion) -> Result<Self, Box<dyn Error>> {
@@ -62,57 +106,245 @@ impl Package {
         Ok(packages)
     }
 
+    /// Switches to the version pinned for this package via the `HBOX_<PACKAGE>_VERSION`
+    /// environment variable or, if unset, in the closest project file.
+    pub fn apply_version_pin(&mut self) -> Result<VersionSource, Box<dyn Error>> {
+        let variable = self.version_variable();
+        if let Ok(version) = env::var(&variable) {
+            self.switch_to_pinned(&version, &variable)?;
+            return Ok(VersionSource::Env(variable));
+        }
+
+        let Some((project_file, project)) = ProjectConfig::load()? else {
+            return Ok(VersionSource::Default);
+        };
+        let Some(version) = project.packages.get(&self.name) else {
+            return Ok(VersionSource::Default);
+        };
+        self.switch_to_pinned(version, &format!("{:?}", project_file))?;
+        Ok(VersionSource::Project(project_file))
+    }
+
+    /// Name of the environment variable pinning this package version, e.g. `HBOX_NODE_VERSION`.
+    pub fn version_variable(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("HBOX_{}_VERSION", name)
+    }
+
+    /// Names of the shims created for this package.
+    pub fn shim_names(&self) -> Vec<String> {
+        let mut shims = Vec::new();
+        if !self.index.only_shim_binaries {
+            shims.push(self.name.clone());
+        }
+        if let Some(binaries) = &self.index.binaries {
+            shims.extend(binaries.iter().map(|binary| binary.name.clone()));
+        }
+        shims
+    }
+
+    /// Command run by the shim named `shim`, either the package name or `package::binary`.
+    pub fn shim_command(&self, shim: &str) -> String {
+        if shim == self.name {
+            shim.to_string()
+        } else {
+            format!("{}::{}", self.name, shim)
+        }
+    }
+
+    /// Name used instead of `shim` when it conflicts with another command, either the alias
+    /// configured in the index or `<package>-<shim>`.
+    pub fn shim_alias(&self, shim: &str) -> String {
+        self.index
+            .aliases
+            .as_ref()
+            .and_then(|aliases| aliases.get(shim))
+            .cloned()
+            .unwrap_or_else(|| format!("{}-{}", self.name, shim))
+    }
+
+    /// Summarizes the package for structured output, with the shims it owns in `shims`.
+    pub fn summary(
+        &self,
+        verbose: bool,
+        shims: &BTreeMap<String, Shim>,
+    ) -> Result<PackageSummary, Box<dyn Error>> {
+        Ok(PackageSummary {
+            name: self.name.clone(),
+            current: self.versions.current.clone(),
+            versions: self.versions.versions.clone(),
+            image: image_reference(self, &Context::from(self))?,
+            binaries: self
+                .index
+                .binaries
+                .iter()
+                .flatten()
+                .map(|binary| binary.name.clone())
+                .collect(),
+            shims: shims
+                .iter()
+                .filter(|(_, shim)| shim.package == self.name)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            index: verbose.then(|| self.index.masked()),
+        })
+    }
+
     pub fn print(&self, verbose: bool) {
-        info!("- [{}]", self.name);
+        report!("- [{}]", self.name);
         if verbose {
-            info!("  - image:");
-            info!("    - name: {}", &self.index.image.name);
+            report!("  - image:");
+            report!("    - name: {}", &self.index.image.name);
             if let Some(build) = &self.index.image.build {
-                info!("    - build:");
-                info!("      - dockerfile: {}", build.dockerfile);
-                info!("      - context: {}", build.context);
-                info!("      - args:");
+                report!("    - build:");
+                if let Some(dockerfile) = &build.dockerfile {
+                    report!("      - dockerfile: {}", dockerfile);
+                }
+                if build.inline.is_some() {
+                    report!("      - dockerfile: (inline)");
+                }
+                if let Some(template) = &build.template {
+                    report!(
+                        "      - template: {:?} {}",
+                        template.name,
+                        template.packages.join(" ")
+                    );
+                }
+                if let Some(context) = &build.context {
+                    report!("      - context: {}", context);
+                }
+                report!("      - args:");
                 if let Some(args) = &build.args {
                     for (arg_name, arg_value) in args.iter() {
-                        info!("        - {}: {}", arg_name, arg_value);
+                        report!("        - {}: {}", arg_name, arg_value.display_value());
                     }
                 }
             }
             if let Some(ports) = &self.index.ports {
-                info!("  - ports:");
+                report!("  - ports:");
                 for port in ports {
-                    info!("    - {}:{}", port.host, port.container);
+                    report!("    - {}:{}", port.host, port.container);
                 }
             }
             if let Some(volumes) = &self.index.volumes {
-                info!("  - volumes:");
+                report!("  - volumes:");
                 for volume in volumes {
-                    info!("    - {}:{}", volume.source, volume.target);
+                    report!("    - {}:{}", volume.source, volume.target);
                 }
             }
             if let Some(environment_variables) = &self.index.environment_variables {
-                info!("  - environment variables:");
+                report!("  - environment variables:");
                 for env_var in environment_variables {
-                    info!("    - {}={}", env_var.name, env_var.value);
+                    if env_var.is_secret() {
+                        report!("    - {}={}", env_var.name, MASK);
+                    } else {
+                        report!("    - {}={}", env_var.name, env_var.value);
+                    }
+                }
+            }
+            if let Some(passthrough) = &self.index.passthrough {
+                report!("  - passthrough:");
+                for pattern in passthrough {
+                    report!("    - {}", pattern);
+                }
+            }
+            if let Some(env_files) = &self.index.env_files {
+                report!("  - env files:");
+                for env_file in env_files {
+                    report!("    - {}", env_file);
                 }
             }
             if let Some(binaries) = &self.index.binaries {
-                info!("  - binaries:");
+                report!("  - binaries:");
                 for binary in binaries {
-                    info!("    - {} -> {}", binary.name, binary.path);
+                    report!("    - {} -> {}", binary.name, binary.path);
                 }
             }
-            info!("  - only_shim_binaries: {}", &self.index.only_shim_binaries);
+            report!("  - only_shim_binaries: {}", &self.index.only_shim_binaries);
             if let Some(current_directory) = &self.index.current_directory {
-                info!("  - current directory: {}", current_directory.clone());
+                report!("  - current directory: {}", current_directory.clone());
+            }
+            if let Some(user) = &self.index.user {
+                report!("  - user: {}", user);
+            }
+            if let Some(network) = &self.index.network {
+                report!("  - network: {}", network);
+            }
+            if let Some(platform) = &self.index.platform {
+                report!("  - platform: {}", platform);
+            }
+            if let Some(entrypoint) = &self.index.entrypoint {
+                report!("  - entrypoint: {}", entrypoint);
+            }
+            if let Some(extra_hosts) = &self.index.extra_hosts {
+                report!("  - extra hosts:");
+                for extra_host in extra_hosts {
+                    report!("    - {}:{}", extra_host.host, extra_host.ip);
+                }
+            }
+            if let Some(devices) = &self.index.devices {
+                report!("  - devices:");
+                for device in devices {
+                    report!(
+                        "    - {}:{}",
+                        device.host,
+                        device.container.as_ref().unwrap_or(&device.host)
+                    );
+                }
+            }
+            if let Some(tmpfs) = &self.index.tmpfs {
+                report!("  - tmpfs:");
+                for mount in tmpfs {
+                    report!("    - {}", mount);
+                }
+            }
+            if let Some(shm_size) = &self.index.shm_size {
+                report!("  - shm size: {}", shm_size);
+            }
+            if self.index.read_only {
+                report!("  - read only: true");
+            }
+            if let Some(run_as_host_user) = &self.index.run_as_host_user {
+                report!("  - run as host user: {}", run_as_host_user);
+            }
+            if self.index.passwd_entry {
+                report!("  - passwd entry: true");
+            }
+            if let Some(capabilities) = &self.index.cap_add {
+                report!("  - capabilities:");
+                for capability in capabilities {
+                    report!("    - {}", capability);
+                }
+            }
+            if let Some(security_options) = &self.index.security_opt {
+                report!("  - security options:");
+                for security_option in security_options {
+                    report!("    - {}", security_option);
+                }
+            }
+            if let Some(labels) = &self.index.labels {
+                report!("  - labels:");
+                for (key, value) in labels.iter() {
+                    report!("    - {}={}", key, value);
+                }
             }
         }
-        info!("  - versions:");
+        report!("  - versions:");
         for version in &self.versions.versions {
             if version == &self.versions.current {
-                info!("    - {} ✔", version);
+                report!("    - {} ✔", version);
             } else {
-                info!("    - {}", version);
+                report!("    - {}", version);
             }
         }
     }
@@ -120,6 +352,19 @@ impl Package {
 
 // Private API
 impl Package {
+    fn switch_to_pinned(&mut self, version: &str, pinned_in: &str) -> Result<(), Box<dyn Error>> {
+        if !self.versions.versions.iter().any(|v| v == version) {
+            return Err(format!(
+                "Version '{}' of '{}' is pinned in {} but it's not installed. Add it first via 'hbox add {} {}'.",
+                version, self.name, pinned_in, self.name, version
+            )
+            .into());
+        }
+        debug!("Using version '{}' pinned in {}", version, pinned_in);
+        self.versions.current = version.to_string();
+        Ok(())
+    }
+
     fn make_from(
         name: &str,
         index_package: PackageIndex,
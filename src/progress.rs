@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::io::{stderr, Write};
+use std::time::{Duration, Instant};
+
+const RENDER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Compact progress of an image pull, built from the engine's pull output. The engine's output
+/// is piped, so it only reports layer states, not downloaded bytes: Docker prints each layer as
+/// `<id>: <status>`, while Podman prints a `Copying blob <digest>` line per layer and
+/// `Writing manifest` once all of them are copied.
+pub struct PullProgress {
+    image: String,
+    started_at: Instant,
+    layers: BTreeMap<String, bool>,
+    live: bool,
+    last_render: Option<Instant>,
+}
+
+impl PullProgress {
+    /// Creates a progress report for `image`. With `live`, it's rendered to stderr on a single
+    /// line while the pull runs, which only makes sense when stderr is a terminal.
+    pub fn new(image: &str, live: bool) -> Self {
+        Self {
+            image: image.to_string(),
+            started_at: Instant::now(),
+            layers: BTreeMap::new(),
+            live,
+            last_render: None,
+        }
+    }
+
+    /// Updates the progress with a line of the engine output, rendering it if it changed.
+    pub fn update(&mut self, line: &str) {
+        if !self.parse(line.trim()) || !self.live {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .last_render
+            .is_none_or(|last| now.duration_since(last) >= RENDER_INTERVAL)
+        {
+            self.last_render = Some(now);
+            let mut stderr = stderr();
+            let _ = write!(stderr, "\r{}\x1b[K", self.status());
+            let _ = stderr.flush();
+        }
+    }
+
+    /// Clears the live progress line, if any.
+    pub fn clear(&self) {
+        if self.live && self.last_render.is_some() {
+            let mut stderr = stderr();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+
+    /// Summary of a finished pull.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Pulled {}", self.image);
+        if !self.layers.is_empty() {
+            summary.push_str(&format!(" ({} layers)", self.layers.len()));
+        }
+        summary.push_str(&format!(
+            " in {:.1}s",
+            self.started_at.elapsed().as_secs_f64()
+        ));
+        summary
+    }
+
+    /// Current progress, like `node:18  3/7 layers`.
+    pub fn status(&self) -> String {
+        let done = self.layers.values().filter(|done| **done).count();
+        format!("{}  {}/{} layers", self.image, done, self.layers.len())
+    }
+
+    /// Parses a line of pull output, returning whether it changed the progress.
+    fn parse(&mut self, line: &str) -> bool {
+        if let Some(blob) = line.strip_prefix("Copying blob ") {
+            let Some(digest) = blob.split_whitespace().next() else {
+                return false;
+            };
+            self.layers.entry(digest.to_string()).or_default();
+            return true;
+        }
+        if line.starts_with("Writing manifest") {
+            self.layers.values_mut().for_each(|done| *done = true);
+            return true;
+        }
+
+        let Some((id, status)) = line.split_once(": ") else {
+            return false;
+        };
+        if id.len() != 12 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return false;
+        }
+        let done = self.layers.entry(id.to_string()).or_default();
+        *done = status.starts_with("Pull complete") || status.starts_with("Already exists");
+        true
+    }
+}
+
+#[test]
+fn verify_pull_progress() {
+    let mut docker = PullProgress::new("node:18", false);
+    for line in [
+        "18: Pulling from library/node",
+        "1a2b3c4d5e6f: Already exists",
+        "0123456789ab: Pulling fs layer",
+        "fedcba987654: Pulling fs layer",
+        "0123456789ab: Verifying Checksum",
+        "0123456789ab: Download complete",
+        "fedcba987654: Waiting",
+    ] {
+        docker.update(line);
+    }
+    assert_eq!(docker.status(), "node:18  1/3 layers");
+
+    for line in [
+        "0123456789ab: Pull complete",
+        "fedcba987654: Download complete",
+        "fedcba987654: Pull complete",
+        "Digest: sha256:abc",
+        "Status: Downloaded newer image for node:18",
+    ] {
+        docker.update(line);
+    }
+    assert_eq!(docker.status(), "node:18  3/3 layers");
+
+    let mut podman = PullProgress::new("node:18", false);
+    for line in [
+        "Trying to pull docker.io/library/node:18...",
+        "Getting image source signatures",
+        "Copying blob sha256:aaa",
+        "Copying blob sha256:bbb",
+    ] {
+        podman.update(line);
+    }
+    assert_eq!(podman.status(), "node:18  0/2 layers");
+
+    for line in [
+        "Copying config sha256:ddd",
+        "Writing manifest to image destination",
+    ] {
+        podman.update(line);
+    }
+    assert_eq!(podman.status(), "node:18  2/2 layers");
+}
@@ -1,45 +1,371 @@
+use crate::build_context::hash_build_context;
+use crate::configs::app::AppConfig;
 use crate::configs::context::Context;
-use crate::configs::index::Binary;
-use crate::configs::user::UserConfig;
+use crate::configs::index::{Binary, Build};
+use crate::configs::user::{Engine, Root, UserConfig};
+use crate::configs::version::VersionConfig;
+use crate::dry_run::{command_line, is_dry_run, plan};
+use crate::host::HostUser;
+use crate::lock::StateLock;
+use crate::logging::{redact_env_args, COMMAND_OUTPUT_TARGET};
+use crate::output::is_quiet;
 use crate::packages::Package;
+use crate::progress::PullProgress;
+use crate::report;
+use crate::serialization::{file_checksum, file_name_part, write_atomically, TempDir};
+use crate::templates::render as render_template;
 use log::{debug, error, info, warn};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use std::io::{stdin, BufRead, BufReader, IsTerminal, Read, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, stdin, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
 use std::thread;
 
-pub fn build(package: &Package) -> bool {
+const CONTAINER_HOME: &str = "/home/hbox";
+/// Exit code of `docker run` and `podman run` when the container couldn't be created, e.g.
+/// because its image is missing.
+const CONTAINER_NOT_CREATED: i32 = 125;
+
+/// Environment variables set for the engine process, like the values of secret build args.
+type EngineEnv = Vec<(String, String)>;
+
+/// Options for building local images.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuildOptions {
+    /// Don't use the build cache of the engine.
+    pub no_cache: bool,
+    /// Always pull newer versions of the base images.
+    pub pull: bool,
+}
+
+pub fn build(
+    package: &Package,
+    options: BuildOptions,
+    quiet: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let context = Context::from(package);
+    let image = image_reference(package, &context)?;
+    build_image(package, &context, image, options, quiet)
+}
+
+fn build_image(
+    package: &Package,
+    context: &Context,
+    image: String,
+    options: BuildOptions,
+    quiet: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let config = UserConfig::load().unwrap_or_default();
+    if use_local_image(&config, &image)? {
+        return Ok(true);
+    }
+
+    let files = BuildFiles::prepare(package, context)?;
+    let paths = (
+        files.dockerfile.to_string_lossy().to_string(),
+        files.context.to_string_lossy().to_string(),
+    );
+    let (mut args, envs) = build_args(package, &config.engine, context, image, &paths, true)?;
+    if options.no_cache {
+        args.insert(1, "--no-cache".to_string());
+    }
+    if options.pull {
+        args.insert(1, "--pull".to_string());
+    }
+
+    if is_dry_run() {
+        plan(format!(
+            "run {}",
+            command_line(config.engine.as_str(), &args)
+        ));
+        return Ok(true);
+    }
+    if quiet {
+        run_command_quietly(config.engine.as_str(), &args, &envs)?;
+        return Ok(true);
+    }
+    Ok(run_command_with_args(config.engine.as_str(), &args, None, &envs).success())
+}
+
+/// Returns a hash of everything a local image is built from: the build command, the Dockerfile
+/// and the files in the build context. Secret build args only contribute their names.
+pub fn build_hash(package: &Package) -> Result<Option<String>, Box<dyn Error>> {
+    if !package.index.image.is_local() {
+        return Ok(None);
+    }
     let config = UserConfig::load().unwrap_or_default();
     let context = Context::from(package);
-    let image_name = context.apply(package.index.image.name.clone());
-    let image = format!("{}:{}", image_name, package.versions.current);
+    let image = image_reference(package, &context)?;
+    let files = BuildFiles::prepare(package, &context)?;
+    let (args, _) = build_args(
+        package,
+        &config.engine,
+        &context,
+        image,
+        &files.labels,
+        false,
+    )?;
 
+    let mut hasher = Sha256::new();
+    for arg in &args {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(file_checksum(&files.dockerfile)?.as_bytes());
+    hash_build_context(&files.context, &mut hasher)?;
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// Dockerfile and context of a local build. Inline and template Dockerfiles are written to a
+/// temporary directory, which is also the context unless the build defines one.
+struct BuildFiles {
+    dockerfile: PathBuf,
+    context: PathBuf,
+    /// How the Dockerfile and context appear in the build hash, since temporary paths change
+    /// on every build.
+    labels: (String, String),
+    _temp_dir: Option<TempDir>,
+}
+
+impl BuildFiles {
+    fn prepare(package: &Package, context: &Context) -> Result<Self, Box<dyn Error>> {
+        let build = package.index.image.build.as_ref().ok_or_else(|| {
+            format!(
+                "Package '{}' doesn't build its image locally.",
+                package.name
+            )
+        })?;
+        let build_context = build
+            .context
+            .as_ref()
+            .map(|build_context| context.apply(build_context.clone()))
+            .transpose()?;
+
+        let (label, dockerfile_text) = match (&build.dockerfile, &build.inline, &build.template) {
+            (Some(dockerfile), None, None) => {
+                let dockerfile = context.apply(dockerfile.clone())?;
+                let build_context = build_context.ok_or_else(|| {
+                    format!("The build of '{}' must define a 'context'.", package.name)
+                })?;
+                return Ok(Self {
+                    dockerfile: PathBuf::from(&dockerfile),
+                    context: PathBuf::from(&build_context),
+                    labels: (dockerfile, build_context),
+                    _temp_dir: None,
+                });
+            }
+            (None, Some(inline), None) => ("<inline>", inline.text()),
+            (None, None, Some(template)) => (
+                "<template>",
+                render_template(template, &package.versions.current),
+            ),
+            _ => {
+                return Err(format!(
+                    "The build of '{}' must define exactly one of 'dockerfile', 'inline' or 'template'.",
+                    package.name
+                )
+                .into())
+            }
+        };
+
+        let temp_dir = TempDir::create("build")?;
+        let dockerfile = temp_dir.path.join("Dockerfile");
+        debug!(
+            "Writing the Dockerfile of '{}' to {:?}",
+            package.name, dockerfile
+        );
+        fs::write(&dockerfile, dockerfile_text)?;
+        let labels = (
+            label.to_string(),
+            build_context.clone().unwrap_or_else(|| label.to_string()),
+        );
+        Ok(Self {
+            dockerfile,
+            context: build_context
+                .map(PathBuf::from)
+                .unwrap_or_else(|| temp_dir.path.clone()),
+            labels,
+            _temp_dir: Some(temp_dir),
+        })
+    }
+}
+
+/// Returns the engine arguments of a build. Inline and template Dockerfiles also get
+/// `IMAGE_NAME` and `IMAGE_VERSION` build args, unless the package defines them.
+fn build_args(
+    package: &Package,
+    engine: &Engine,
+    context: &Context,
+    image: String,
+    (dockerfile, build_context): &(String, String),
+    resolve_secrets: bool,
+) -> Result<(Vec<String>, EngineEnv), Box<dyn Error>> {
+    let build = package.index.image.build.as_ref().ok_or_else(|| {
+        format!(
+            "Package '{}' doesn't build its image locally.",
+            package.name
+        )
+    })?;
     let mut args = vec!["build".to_string(), "-t".to_string(), image];
-    if let Some(build) = &package.index.image.build {
-        args.push("-f".to_string());
-        args.push(build.dockerfile.clone());
-        if let Some(build_args) = &build.args {
-            for (key, value) in build_args {
+    let mut envs = Vec::new();
+    if let Some(platform) = image_platform(package) {
+        args.push("--platform".to_string());
+        args.push(platform.clone());
+    }
+    args.push("-f".to_string());
+    args.push(dockerfile.clone());
+
+    let build_args = build.args.clone().unwrap_or_default();
+    if build.dockerfile.is_none() {
+        let standard_args = [
+            (
+                "IMAGE_NAME",
+                context.apply(package.index.image.name.clone())?,
+            ),
+            ("IMAGE_VERSION", package.versions.current.clone()),
+        ];
+        for (key, value) in standard_args {
+            if !build_args.contains_key(key) {
                 args.push("--build-arg".to_string());
-                args.push(format!("{}={}", key, context.apply(value.clone())));
+                args.push(format!("{}={}", key, value));
             }
         }
-        args.push(build.context.clone());
     }
 
-    run_command_with_args(config.engine.as_str(), &args, None)
+    let mut build_args: Vec<_> = build_args.iter().collect();
+    build_args.sort_by_key(|(key, _)| *key);
+    for (key, value) in build_args {
+        args.push("--build-arg".to_string());
+        if value.is_secret() {
+            // Without a value, the engine reads the build arg from its own environment.
+            args.push(key.clone());
+            if resolve_secrets {
+                envs.push((key.clone(), value.resolve(context)?));
+            }
+        } else {
+            args.push(format!("{}={}", key, value.resolve(context)?));
+        }
+    }
+    add_build_options(
+        build,
+        engine,
+        context,
+        resolve_secrets,
+        &mut args,
+        &mut envs,
+    )?;
+    args.push(build_context.clone());
+    Ok((args, envs))
+}
+
+/// Adds the target stage, labels, cache sources, secrets and SSH agents of a build. Docker only
+/// supports secrets and SSH with BuildKit, so it's enabled for those builds, and Podman only
+/// takes repositories without a tag as cache sources.
+fn add_build_options(
+    build: &Build,
+    engine: &Engine,
+    context: &Context,
+    check_secrets: bool,
+    args: &mut Vec<String>,
+    envs: &mut EngineEnv,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(target) = &build.target {
+        args.push("--target".to_string());
+        args.push(target.clone());
+    }
+    if let Some(labels) = &build.labels {
+        let mut labels: Vec<_> = labels.iter().collect();
+        labels.sort();
+        for (key, value) in labels {
+            args.push("--label".to_string());
+            args.push(format!("{}={}", key, context.apply(value.clone())?));
+        }
+    }
+    for image in build.cache_from.iter().flatten() {
+        args.push("--cache-from".to_string());
+        args.push(match engine {
+            Engine::Docker => image.clone(),
+            Engine::Podman => without_tag(image).to_string(),
+        });
+    }
+
+    let secrets = build.secrets.as_deref().unwrap_or_default();
+    for secret in secrets {
+        let src = context.apply(secret.src.clone())?;
+        if check_secrets && !Path::new(&src).is_file() {
+            return Err(format!("Build secret '{}' not found at '{}'.", secret.id, src).into());
+        }
+        args.push("--secret".to_string());
+        args.push(format!("id={},src={}", secret.id, src));
+    }
+    let ssh = build.ssh.as_deref().unwrap_or_default();
+    for agent in ssh {
+        args.push("--ssh".to_string());
+        args.push(context.apply(agent.clone())?);
+    }
+    if *engine == Engine::Docker && (!secrets.is_empty() || !ssh.is_empty()) {
+        envs.push(("DOCKER_BUILDKIT".to_string(), "1".to_string()));
+    }
+    Ok(())
+}
+
+/// Removes the tag from an image reference, keeping registry ports like `localhost:5000`.
+fn without_tag(image: &str) -> &str {
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    match image[name_start..].rfind(':') {
+        Some(i) => &image[..name_start + i],
+        None => image,
+    }
+}
+
+pub fn pull(package: &Package, quiet: bool) -> Result<bool, Box<dyn Error>> {
+    let image = image_reference(package, &Context::from(package))?;
+    pull_image(package, image, quiet)
 }
 
-pub fn pull(package: &Package) -> bool {
+fn pull_image(package: &Package, image: String, quiet: bool) -> Result<bool, Box<dyn Error>> {
     let config = UserConfig::load().unwrap_or_default();
-    let image_name = Context::from(package).apply(package.index.image.name.clone());
-    let image = format!("{}:{}", image_name, package.versions.current);
-    run_command_with_args(config.engine.as_str(), &["pull".to_string(), image], None)
+    if use_local_image(&config, &image)? {
+        return Ok(true);
+    }
+
+    let mut args = vec!["pull".to_string()];
+    add_platform(package, &mut args);
+    args.push(image.clone());
+
+    if is_dry_run() {
+        plan(format!(
+            "run {}",
+            command_line(config.engine.as_str(), &args)
+        ));
+        return Ok(true);
+    }
+    if quiet || is_quiet() {
+        run_command_quietly(config.engine.as_str(), &args, &[])?;
+        return Ok(true);
+    }
+    run_pull_with_progress(config.engine.as_str(), &args, &image)
 }
 
-pub fn run(package: &Package, binary: Option<String>, params: &Vec<String>) -> bool {
+pub fn run(
+    package: &Package,
+    binary: Option<String>,
+    params: &[String],
+    environment: &[String],
+) -> Result<i32, Box<dyn Error>> {
     let config = UserConfig::load().unwrap_or_default();
+    if is_dry_run() {
+        let args = dry_run(package, binary, params, environment)?;
+        plan(format!("run {}", command_line(&args[0], &args[1..])));
+        return Ok(0);
+    }
+    let (context, image) = binary_image(package, &binary)?;
+    refresh_image(package, &context, &image, &config)?;
 
     let interactive = !stdin().is_terminal();
     let mut buffer = Vec::new();
@@ -49,6 +375,62 @@ pub fn run(package: &Package, binary: Option<String>, params: &Vec<String>) -> b
             .expect("Failed to read stdin");
     }
 
+    let (args, envs) = run_args(
+        package,
+        binary,
+        params,
+        environment,
+        interactive,
+        &config,
+        false,
+    )?;
+
+    // Images are never pulled by the engine while running, so a missing image is only looked
+    // for when the container couldn't be created, instead of before every run.
+    let mut status =
+        run_command_with_args(config.engine.as_str(), &args, Some(buffer.clone()), &envs);
+    if status.code() == Some(CONTAINER_NOT_CREATED) && image_digest(&image).is_none() {
+        get_missing_image(package, &context, &image, &config)?;
+        status = run_command_with_args(config.engine.as_str(), &args, Some(buffer), &envs);
+    }
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Returns the engine command line `run` would execute for a package, without running it.
+/// Secret environment variables only appear by name, as their values are passed to the engine
+/// through its environment.
+pub fn dry_run(
+    package: &Package,
+    binary: Option<String>,
+    params: &[String],
+    environment: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let config = UserConfig::load().unwrap_or_default();
+    let interactive = !stdin().is_terminal();
+    let (args, _) = run_args(
+        package,
+        binary,
+        params,
+        environment,
+        interactive,
+        &config,
+        true,
+    )?;
+    Ok([config.engine.as_str().to_string()]
+        .into_iter()
+        .chain(args)
+        .collect())
+}
+
+fn run_args(
+    package: &Package,
+    binary: Option<String>,
+    params: &[String],
+    environment: &[String],
+    interactive: bool,
+    config: &Root,
+    dry_run: bool,
+) -> Result<(Vec<String>, EngineEnv), Box<dyn Error>> {
     let mut args = vec!["run".to_string()];
     args.push(if interactive {
         "-i".to_string()
@@ -56,15 +438,33 @@ pub fn run(package: &Package, binary: Option<String>, params: &Vec<String>) -> b
         "-it".to_string()
     });
 
+    let (context, image) = binary_image(package, &binary)?;
+    let context = context.with_image(&image);
     let binary = get_binary(package, &binary);
 
     add_default_flags(package, &mut args);
+    add_user(package, &mut args);
+    add_host_user(package, config, &image, dry_run, &mut args);
+    add_network(package, &mut args);
+    add_platform(package, &mut args);
     add_ports(package, &mut args);
-    add_volumes(package, &mut args);
-    add_current_directory(package, &mut args);
-    add_environment_variables(package, &mut args);
-    add_binary_entrypoint(binary, &mut args);
-    add_container_image(package, &mut args);
+    add_extra_hosts(package, &mut args);
+    add_volumes(package, &context, &mut args)?;
+    add_tmpfs(package, &mut args);
+    add_devices(package, &mut args);
+    add_read_only(package, &config.engine, &mut args);
+    add_shm_size(package, &mut args);
+    add_capabilities(package, &mut args);
+    add_security_options(package, &mut args);
+    add_labels(package, &mut args);
+    add_current_directory(package, &context, &mut args)?;
+    add_env_files(package, &context, &mut args)?;
+    add_passthrough_variables(package, &mut args);
+    let mut envs = Vec::new();
+    add_environment_variables(package, &context, &mut args, &mut envs)?;
+    add_runtime_environment_variables(environment, &mut args, &mut envs);
+    add_entrypoint(package, binary, &mut args);
+    args.push(image);
     add_binary_cmd(binary, &mut args);
 
     if should_wrap_args(binary) {
@@ -78,11 +478,295 @@ pub fn run(package: &Package, binary: Option<String>, params: &Vec<String>) -> b
         args.extend(params.iter().cloned());
     }
 
-    run_command_with_args(config.engine.as_str(), &args, Some(buffer))
+    Ok((args, envs))
+}
+
+/// In offline mode, returns whether the image is already available locally, so it doesn't need
+/// to be pulled or built, and fails otherwise.
+fn use_local_image(config: &Root, image: &str) -> Result<bool, Box<dyn Error>> {
+    if !config.offline {
+        return Ok(false);
+    }
+    if image_digest(image).is_some() {
+        info!("Offline mode is enabled, using local image '{}'", image);
+        Ok(true)
+    } else {
+        Err(format!(
+            "Image '{}' isn't available locally and hbox is in offline mode.",
+            image
+        )
+        .into())
+    }
+}
+
+/// Resolves the image a package runs, which can depend on the binary, with its context.
+fn binary_image(
+    package: &Package,
+    binary: &Option<String>,
+) -> Result<(Context, String), Box<dyn Error>> {
+    let binary = get_binary(package, binary);
+    let context = Context::from(package).with_binary(binary.map(|b| b.name.as_str()));
+    let image = image_reference(package, &context)?;
+    Ok((context, image))
+}
+
+/// Rebuilds the local image of a package before running it if what it's built from changed and
+/// `images.rebuild_on_change` is enabled.
+fn refresh_image(
+    package: &Package,
+    context: &Context,
+    image: &str,
+    config: &Root,
+) -> Result<(), Box<dyn Error>> {
+    if !config.images.rebuild_on_change || config.offline || !build_changed(package) {
+        return Ok(());
+    }
+    warn!(
+        "The build of '{}' version '{}' changed, rebuilding image '{}'",
+        package.name, package.versions.current, image
+    );
+    if rebuild_image(package, context, image)? {
+        Ok(())
+    } else {
+        Err(format!("Failed to rebuild image '{}'.", image).into())
+    }
+}
+
+/// How to get the image of a package again when it's missing while running it.
+#[derive(Debug, PartialEq)]
+enum MissingImage {
+    Pull,
+    Build,
+    Fail(String),
+}
+
+fn missing_image(package: &Package, image: &str, config: &Root) -> MissingImage {
+    if config.offline {
+        MissingImage::Fail(format!(
+            "Image '{}' isn't available locally and hbox is in offline mode.",
+            image
+        ))
+    } else if !config.images.auto_pull {
+        MissingImage::Fail(format!(
+            "Image '{}' isn't available locally. Run 'hbox add {}@{}' to get it again.",
+            image, package.name, package.versions.current
+        ))
+    } else if package.index.image.is_local() {
+        MissingImage::Build
+    } else {
+        MissingImage::Pull
+    }
+}
+
+/// Gets the missing image of a package again, pulling or building it if `images.auto_pull` is
+/// enabled. The output is kept quiet, so it doesn't mix with the output of the package.
+fn get_missing_image(
+    package: &Package,
+    context: &Context,
+    image: &str,
+    config: &Root,
+) -> Result<(), Box<dyn Error>> {
+    let available = match missing_image(package, image, config) {
+        MissingImage::Fail(message) => return Err(message.into()),
+        MissingImage::Build => {
+            warn!(
+                "Image '{}' isn't available locally, building it again",
+                image
+            );
+            rebuild_image(package, context, image)?
+        }
+        MissingImage::Pull => {
+            warn!(
+                "Image '{}' isn't available locally, pulling it again",
+                image
+            );
+            pull_image(package, image.to_string(), true)?
+        }
+    };
+    if available {
+        Ok(())
+    } else {
+        Err(format!("Failed to get image '{}'.", image).into())
+    }
+}
+
+/// Indicates if what a local image is built from changed since it was built. Versions added
+/// before build hashes were recorded are never considered changed.
+fn build_changed(package: &Package) -> bool {
+    let Some(built) = package.versions.builds.get(&package.versions.current) else {
+        return false;
+    };
+    match build_hash(package) {
+        Ok(Some(hash)) => &hash != built,
+        Ok(None) => false,
+        Err(e) => {
+            warn!(
+                "Could not check if the image of '{}' must be rebuilt: {}",
+                package.name, e
+            );
+            false
+        }
+    }
+}
+
+/// Quietly builds a local image again while running a package, recording what it was built
+/// from.
+fn rebuild_image(
+    package: &Package,
+    context: &Context,
+    image: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let _lock = StateLock::acquire()?;
+    let hash = build_hash(package);
+    let built = build_image(
+        package,
+        context,
+        image.to_string(),
+        BuildOptions::default(),
+        true,
+    )?;
+    if let (true, Ok(Some(hash))) = (built, hash) {
+        VersionConfig::save_build_hash(&package.name, &package.versions.current, hash)?;
+    }
+    Ok(built)
+}
+
+pub fn remove_image(image: &str) -> bool {
+    let config = UserConfig::load().unwrap_or_default();
+    let args = vec!["image".to_string(), "rm".to_string(), image.to_string()];
+    if is_dry_run() {
+        plan(format!(
+            "run {}",
+            command_line(config.engine.as_str(), &args)
+        ));
+        return true;
+    }
+    run_command_with_args(config.engine.as_str(), &args, None, &[]).success()
+}
+
+/// Streams an image archive, as created by `docker save`, into `writer`.
+pub fn save_image(image: &str, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let config = UserConfig::load().unwrap_or_default();
+    let args = vec!["save".to_string(), image.to_string()];
+    if is_dry_run() {
+        plan(format!(
+            "run {}",
+            command_line(config.engine.as_str(), &args)
+        ));
+        return Ok(());
+    }
+    debug!(
+        "Running command: {} {}",
+        config.engine.as_str(),
+        args.join(" ")
+    );
+
+    let mut child = Command::new(config.engine.as_str())
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr = collect_output(child.stderr.take());
+    let copied = io::copy(&mut child.stdout.take().expect("stdout is piped"), writer);
+    finish_streaming(config.engine.as_str(), &args, child, [None, stderr], copied)
+}
+
+/// Loads the images of an archive streamed from `reader`, as created by [`save_image`].
+pub fn load_image(reader: &mut impl Read) -> Result<(), Box<dyn Error>> {
+    let config = UserConfig::load().unwrap_or_default();
+    let args = vec!["load".to_string()];
+    if is_dry_run() {
+        plan(format!(
+            "run {}",
+            command_line(config.engine.as_str(), &args)
+        ));
+        return Ok(());
+    }
+    debug!(
+        "Running command: {} {}",
+        config.engine.as_str(),
+        args.join(" ")
+    );
+
+    let mut child = Command::new(config.engine.as_str())
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let output = [
+        collect_output(child.stdout.take()),
+        collect_output(child.stderr.take()),
+    ];
+    let copied = io::copy(reader, &mut child.stdin.take().expect("stdin is piped"));
+    finish_streaming(config.engine.as_str(), &args, child, output, copied)
+}
+
+/// Reads an output of a child process in the background, so it can't fill up and block the
+/// process while another of its streams is copied.
+fn collect_output<R: Read + Send + 'static>(
+    reader: Option<R>,
+) -> Option<thread::JoinHandle<String>> {
+    let mut reader = reader?;
+    Some(thread::spawn(move || {
+        let mut output = String::new();
+        let _ = reader.read_to_string(&mut output);
+        output
+    }))
+}
+
+/// Waits for a process whose stdin or stdout was streamed, failing if either the process or
+/// the copy failed. `output` holds its collected stdout and stderr.
+fn finish_streaming(
+    command: &str,
+    args: &[String],
+    mut child: Child,
+    output: [Option<thread::JoinHandle<String>>; 2],
+    copied: io::Result<u64>,
+) -> Result<(), Box<dyn Error>> {
+    if copied.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    let [stdout, stderr] =
+        output.map(|reader| reader.and_then(|r| r.join().ok()).unwrap_or_default());
+    for line in stdout.lines().chain(stderr.lines()) {
+        debug!(target: COMMAND_OUTPUT_TARGET, "{}", line);
+    }
+
+    if !status.success() {
+        return Err(command_failed(command, args, &stderr));
+    }
+    copied?;
+    Ok(())
+}
+
+pub fn image_digest(image: &str) -> Option<String> {
+    let config = UserConfig::load().unwrap_or_default();
+    let output = Command::new(config.engine.as_str())
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        let digest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (!digest.is_empty()).then_some(digest)
+    } else {
+        None
+    }
+}
+
+pub fn image_reference(package: &Package, context: &Context) -> Result<String, Box<dyn Error>> {
+    Ok(format!(
+        "{}:{}",
+        context.apply(package.index.image.name.clone())?,
+        package.versions.current
+    ))
 }
 
 fn should_wrap_args(binary: Option<&Binary>) -> bool {
-    binary.map_or(false, |bin| bin.wrap_args)
+    binary.is_some_and(|bin| bin.wrap_args)
 }
 
 fn generate_random_name(package: &Package) -> String {
@@ -98,29 +782,28 @@ fn add_default_flags(package: &Package, args: &mut Vec<String>) {
     args.push("--rm".to_string());
     args.push("--name".to_string());
     args.push(generate_random_name(package));
+    args.push("--pull".to_string());
+    args.push("never".to_string());
 }
 
-fn add_container_image(package: &Package, args: &mut Vec<String>) {
-    let context = Context::from(&package);
-    args.push(format!(
-        "{}:{}",
-        context.apply(package.index.image.name.clone()),
-        package.versions.current
-    ));
-}
-
-fn add_volumes(package: &Package, args: &mut Vec<String>) {
+fn add_volumes(
+    package: &Package,
+    context: &Context,
+    args: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
     if let Some(volumes) = &package.index.volumes {
         for volume in volumes {
-            let source = shellexpand::full(&volume.source).unwrap();
-            if Path::new(&source.to_string()).exists() {
+            let source = context.apply(volume.source.clone())?;
+            let target = context.apply(volume.target.clone())?;
+            if Path::new(&source).exists() {
                 args.push("-v".to_string());
-                args.push(format!("{}:{}", &source, volume.target));
+                args.push(format!("{}:{}", source, target));
             } else {
                 warn!("Volume source '{}' not found. Skipping.", source);
             }
         }
     }
+    Ok(())
 }
 
 fn add_ports(package: &Package, args: &mut Vec<String>) {
@@ -132,27 +815,387 @@ fn add_ports(package: &Package, args: &mut Vec<String>) {
     }
 }
 
-fn add_current_directory(package: &Package, args: &mut Vec<String>) {
+fn add_current_directory(
+    package: &Package,
+    context: &Context,
+    args: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
     if let Some(current_directory) = &package.index.current_directory {
         args.push("-w".to_string());
-        args.push(current_directory.clone());
+        args.push(context.apply(current_directory.clone())?);
     }
+    Ok(())
 }
 
-fn add_environment_variables(package: &Package, args: &mut Vec<String>) {
+/// Adds the environment variables of the package. Secrets only appear by name on the command
+/// line, and their values are passed to the engine through its environment, so they are
+/// neither written to disk nor visible in the process list.
+fn add_environment_variables(
+    package: &Package,
+    context: &Context,
+    args: &mut Vec<String>,
+    envs: &mut EngineEnv,
+) -> Result<(), Box<dyn Error>> {
     if let Some(environment_variables) = &package.index.environment_variables {
         for env_var in environment_variables {
-            let expanded_value = shellexpand::full(&env_var.value).unwrap_or_default();
+            let value = env_var.resolve(context)?;
             args.push("-e".to_string());
-            args.push(format!("{}={}", env_var.name, expanded_value));
+            if env_var.is_secret() {
+                args.push(env_var.name.clone());
+                envs.push((env_var.name.clone(), value));
+            } else {
+                args.push(format!("{}={}", env_var.name, value));
+            }
         }
     }
+    Ok(())
 }
 
-fn add_binary_entrypoint(binary: Option<&Binary>, args: &mut Vec<String>) {
-    if let Some(binary) = binary {
+fn add_env_files(
+    package: &Package,
+    context: &Context,
+    args: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(env_files) = &package.index.env_files {
+        for env_file in env_files {
+            let path = context.apply(env_file.clone())?;
+            if Path::new(&path).is_file() {
+                args.push("--env-file".to_string());
+                args.push(path);
+            } else {
+                warn!("Env file '{}' not found. Skipping.", path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_passthrough_variables(package: &Package, args: &mut Vec<String>) {
+    if let Some(patterns) = &package.index.passthrough {
+        let mut names: Vec<String> = env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| {
+                patterns
+                    .iter()
+                    .any(|pattern| matches_pattern(pattern, name))
+            })
+            .collect();
+        names.sort();
+        for name in names {
+            // Passing only the name makes the engine read the value from its own
+            // environment, so it never shows up in the command line.
+            args.push("-e".to_string());
+            args.push(name);
+        }
+    }
+}
+
+/// Adds the variables given with `hbox run -e`. Like secrets, their values are passed through
+/// the environment of the engine, as they may be secrets too.
+fn add_runtime_environment_variables(
+    environment: &[String],
+    args: &mut Vec<String>,
+    envs: &mut EngineEnv,
+) {
+    for env_var in environment {
+        args.push("-e".to_string());
+        match env_var.split_once('=') {
+            Some((name, value)) => {
+                args.push(name.to_string());
+                envs.push((name.to_string(), value.to_string()));
+            }
+            None => args.push(env_var.clone()),
+        }
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let name = &name[prefix.len()..];
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+fn add_user(package: &Package, args: &mut Vec<String>) {
+    if let Some(user) = &package.index.user {
+        args.push("--user".to_string());
+        args.push(user.clone());
+    }
+}
+
+/// Runs the container as the host user. With `dry_run`, the home directory and passwd files are
+/// only reported, as they would be created when running the package.
+fn add_host_user(
+    package: &Package,
+    config: &Root,
+    image: &str,
+    dry_run: bool,
+    args: &mut Vec<String>,
+) {
+    if package.index.user.is_some()
+        || !package
+            .index
+            .run_as_host_user
+            .unwrap_or(config.run_as_host_user)
+    {
+        return;
+    }
+
+    let Some(host_user) = HostUser::current() else {
+        debug!("Running as host user is not supported on this platform. Skipping.");
+        return;
+    };
+
+    match config.engine {
+        Engine::Docker => {
+            args.push("--user".to_string());
+            args.push(format!("{}:{}", host_user.uid, host_user.gid));
+            if package.index.passwd_entry {
+                add_passwd_entry(config, &host_user, image, dry_run, args);
+            }
+        }
+        // Podman maps the host user into the container and adds it to /etc/passwd.
+        Engine::Podman => args.push("--userns=keep-id".to_string()),
+    }
+
+    let home = AppConfig::load().homes_path().join(&package.name);
+    if dry_run {
+        if !home.exists() && is_dry_run() {
+            plan(format!("create directory {}", home.display()));
+        }
+    } else if let Err(e) = fs::create_dir_all(&home) {
+        warn!(
+            "Could not create home directory {:?}: {}. Skipping.",
+            home, e
+        );
+        return;
+    }
+    args.push("-v".to_string());
+    args.push(format!("{}:{}", home.display(), CONTAINER_HOME));
+    args.push("-e".to_string());
+    args.push(format!("HOME={}", CONTAINER_HOME));
+}
+
+/// Mounts `passwd` and `group` files holding the entries of the image plus the host user. They
+/// are generated once per image and host user, and reused by the following runs.
+fn add_passwd_entry(
+    config: &Root,
+    host_user: &HostUser,
+    image: &str,
+    dry_run: bool,
+    args: &mut Vec<String>,
+) {
+    let dir = AppConfig::load().homes_path().join(".etc").join(format!(
+        "{}-{}-{}",
+        file_name_part(image),
+        host_user.uid,
+        host_user.gid
+    ));
+    let passwd_file = dir.join("passwd");
+    let group_file = dir.join("group");
+
+    if dry_run {
+        if !(passwd_file.exists() && group_file.exists()) && is_dry_run() {
+            plan(format!("write {}", passwd_file.display()));
+            plan(format!("write {}", group_file.display()));
+        }
+    } else if !passwd_file.exists() || !group_file.exists() {
+        let passwd = with_entry(
+            image_file(config, image, "/etc/passwd")
+                .unwrap_or_else(|| "root:x:0:0:root:/root:/bin/sh\n".to_string()),
+            host_user.uid,
+            format!(
+                "{}:x:{}:{}:{}:{}:/bin/sh",
+                host_user.name, host_user.uid, host_user.gid, host_user.name, CONTAINER_HOME
+            ),
+        );
+        let group = with_entry(
+            image_file(config, image, "/etc/group").unwrap_or_else(|| "root:x:0:\n".to_string()),
+            host_user.gid,
+            format!("{}:x:{}:", host_user.name, host_user.gid),
+        );
+
+        let result = fs::create_dir_all(&dir)
+            .map_err(|e| e.into())
+            .and_then(|_| write_atomically(&passwd_file, passwd.as_bytes()))
+            .and_then(|_| write_atomically(&group_file, group.as_bytes()));
+        if let Err(e) = result {
+            warn!("Could not create passwd entry: {}. Skipping.", e);
+            return;
+        }
+    }
+
+    args.push("-v".to_string());
+    args.push(format!("{}:/etc/passwd:ro", passwd_file.display()));
+    args.push("-v".to_string());
+    args.push(format!("{}:/etc/group:ro", group_file.display()));
+}
+
+/// Appends an entry to the lines of a `passwd` or `group` file, unless its id is already there.
+fn with_entry(mut contents: String, id: u32, entry: String) -> String {
+    let id = id.to_string();
+    if contents
+        .lines()
+        .any(|line| line.split(':').nth(2) == Some(id.as_str()))
+    {
+        return contents;
+    }
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents + &entry + "\n"
+}
+
+/// Returns the contents of a file in an image, or `None` if it can't be read, like in images
+/// without `cat`. The image is never pulled, so a missing image is left to `run`, which
+/// follows `offline` and `images.auto_pull`.
+fn image_file(config: &Root, image: &str, path: &str) -> Option<String> {
+    let output = Command::new(config.engine.as_str())
+        .args([
+            "run",
+            "--rm",
+            "--pull",
+            "never",
+            "--entrypoint",
+            "cat",
+            image,
+            path,
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let contents = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() && !contents.trim().is_empty() {
+        Some(contents)
+    } else {
+        debug!("Could not read {} from image '{}'", path, image);
+        None
+    }
+}
+
+fn add_network(package: &Package, args: &mut Vec<String>) {
+    if let Some(network) = &package.index.network {
+        args.push("--network".to_string());
+        args.push(network.clone());
+    }
+}
+
+/// Platform of the image, which is the platform of its build, if any, so a locally built image
+/// runs on the platform it was built for.
+fn image_platform(package: &Package) -> Option<&String> {
+    package
+        .index
+        .image
+        .build
+        .as_ref()
+        .and_then(|build| build.platform.as_ref())
+        .or(package.index.platform.as_ref())
+}
+
+fn add_platform(package: &Package, args: &mut Vec<String>) {
+    if let Some(platform) = image_platform(package) {
+        args.push("--platform".to_string());
+        args.push(platform.clone());
+    }
+}
+
+fn add_extra_hosts(package: &Package, args: &mut Vec<String>) {
+    if let Some(extra_hosts) = &package.index.extra_hosts {
+        for extra_host in extra_hosts {
+            args.push("--add-host".to_string());
+            args.push(format!("{}:{}", extra_host.host, extra_host.ip));
+        }
+    }
+}
+
+fn add_tmpfs(package: &Package, args: &mut Vec<String>) {
+    if let Some(tmpfs) = &package.index.tmpfs {
+        for mount in tmpfs {
+            args.push("--tmpfs".to_string());
+            args.push(mount.clone());
+        }
+    }
+}
+
+fn add_devices(package: &Package, args: &mut Vec<String>) {
+    if let Some(devices) = &package.index.devices {
+        for device in devices {
+            let container = device.container.as_ref().unwrap_or(&device.host);
+            args.push("--device".to_string());
+            match &device.permissions {
+                Some(permissions) => {
+                    args.push(format!("{}:{}:{}", device.host, container, permissions))
+                }
+                None => args.push(format!("{}:{}", device.host, container)),
+            }
+        }
+    }
+}
+
+fn add_read_only(package: &Package, engine: &Engine, args: &mut Vec<String>) {
+    if package.index.read_only {
+        args.push("--read-only".to_string());
+        // Podman mounts writable tmpfs on /tmp, /var/tmp and /run for read-only
+        // containers by default, while Docker doesn't. Keep both engines consistent.
+        if *engine == Engine::Podman {
+            args.push("--read-only-tmpfs=false".to_string());
+        }
+    }
+}
+
+fn add_shm_size(package: &Package, args: &mut Vec<String>) {
+    if let Some(shm_size) = &package.index.shm_size {
+        args.push("--shm-size".to_string());
+        args.push(shm_size.clone());
+    }
+}
+
+fn add_capabilities(package: &Package, args: &mut Vec<String>) {
+    if let Some(capabilities) = &package.index.cap_add {
+        for capability in capabilities {
+            args.push("--cap-add".to_string());
+            args.push(capability.clone());
+        }
+    }
+}
+
+fn add_security_options(package: &Package, args: &mut Vec<String>) {
+    if let Some(security_options) = &package.index.security_opt {
+        for security_option in security_options {
+            args.push("--security-opt".to_string());
+            args.push(security_option.clone());
+        }
+    }
+}
+
+fn add_labels(package: &Package, args: &mut Vec<String>) {
+    if let Some(labels) = &package.index.labels {
+        let mut labels: Vec<_> = labels.iter().collect();
+        labels.sort();
+        for (key, value) in labels {
+            args.push("--label".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+    }
+}
+
+fn add_entrypoint(package: &Package, binary: Option<&Binary>, args: &mut Vec<String>) {
+    let entrypoint = match binary {
+        Some(binary) => Some(&binary.path),
+        None => package.index.entrypoint.as_ref(),
+    };
+    if let Some(entrypoint) = entrypoint {
         args.push("--entrypoint".to_string());
-        args.push(binary.path.to_string());
+        args.push(entrypoint.to_string());
     }
 }
 
@@ -202,14 +1245,24 @@ fn get_stdio(
     (stdin, stdout, stderr)
 }
 
-fn run_command_with_args(command: &str, args: &[String], stdin_buffer: Option<Vec<u8>>) -> bool {
-    debug!("Running command: {} {}", command, args.join(" "));
+fn run_command_with_args(
+    command: &str,
+    args: &[String],
+    stdin_buffer: Option<Vec<u8>>,
+    envs: &[(String, String)],
+) -> ExitStatus {
+    debug!(
+        "Running command: {} {}",
+        command,
+        redact_env_args(args).join(" ")
+    );
 
     let config = UserConfig::load().unwrap_or_default();
     let (stdin, stdout, stderr) = get_stdio(&config, &stdin_buffer);
 
     let mut child = Command::new(command)
         .args(args)
+        .envs(envs.iter().cloned())
         .stdout(stdout)
         .stderr(stderr)
         .stdin(stdin)
@@ -227,12 +1280,18 @@ fn run_command_with_args(command: &str, args: &[String], stdin_buffer: Option<Ve
 
     let stdout_thread = spawn_log_thread(
         child.stdout.take(),
-        |line| info!("{}", line),
+        |line| {
+            println!("{}", line);
+            info!(target: COMMAND_OUTPUT_TARGET, "{}", line);
+        },
         config.experimental.capture_stdout,
     );
     let stderr_thread = spawn_log_thread(
         child.stderr.take(),
-        |line| error!("{}", line),
+        |line| {
+            eprintln!("{}", line);
+            error!(target: COMMAND_OUTPUT_TARGET, "{}", line);
+        },
         config.experimental.capture_stderr,
     );
 
@@ -246,7 +1305,116 @@ fn run_command_with_args(command: &str, args: &[String], stdin_buffer: Option<Ve
         let _ = thread.join();
     }
 
-    status.success()
+    status
+}
+
+/// Runs a pull command, rendering a compact progress from its output instead of printing it.
+/// The progress is updated live when stderr is a terminal; otherwise, only a summary is printed.
+fn run_pull_with_progress(
+    command: &str,
+    args: &[String],
+    image: &str,
+) -> Result<bool, Box<dyn Error>> {
+    debug!(
+        "Running command: {} {}",
+        command,
+        redact_env_args(args).join(" ")
+    );
+
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Docker reports progress on stdout and Podman on stderr, so both are parsed.
+    let (sender, receiver) = mpsc::channel();
+    let readers = [
+        forward_lines(child.stdout.take(), sender.clone(), false),
+        forward_lines(child.stderr.take(), sender, true),
+    ];
+
+    let mut progress = PullProgress::new(image, std::io::stderr().is_terminal());
+    let mut errors = Vec::new();
+    for (line, is_stderr) in receiver {
+        debug!(target: COMMAND_OUTPUT_TARGET, "{}", line);
+        progress.update(&line);
+        if is_stderr {
+            errors.push(line);
+        }
+    }
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    let status = child.wait()?;
+
+    progress.clear();
+    if status.success() {
+        report!("{}", progress.summary());
+    } else {
+        for line in errors.iter().filter(|line| !line.starts_with("Copying ")) {
+            error!("{}", line);
+        }
+    }
+    Ok(status.success())
+}
+
+fn forward_lines<R: Read + Send + 'static>(
+    reader: Option<R>,
+    sender: mpsc::Sender<(String, bool)>,
+    is_stderr: bool,
+) -> Option<thread::JoinHandle<()>> {
+    let reader = reader?;
+    Some(thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if sender.send((line, is_stderr)).is_err() {
+                break;
+            }
+        }
+    }))
+}
+
+/// Runs a command without printing its output, which is logged instead. It's used when several
+/// commands run at the same time, so their output doesn't interleave. Fails with the last line
+/// of the command's stderr if the command fails.
+fn run_command_quietly(
+    command: &str,
+    args: &[String],
+    envs: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Running command: {} {}",
+        command,
+        redact_env_args(args).join(" ")
+    );
+
+    let output = Command::new(command)
+        .args(args)
+        .envs(envs.iter().cloned())
+        .stdin(Stdio::null())
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stdout.lines().chain(stderr.lines()) {
+        debug!(target: COMMAND_OUTPUT_TARGET, "{}", line);
+    }
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(command_failed(command, args, &stderr))
+    }
+}
+
+/// Error of a failed engine command, with the last line it printed to stderr.
+fn command_failed(command: &str, args: &[String], stderr: &str) -> Box<dyn Error> {
+    let reason = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("no output");
+    format!("'{} {}' failed: {}", command, args[0], reason.trim()).into()
 }
 
 fn spawn_log_thread<R: Read + Send + 'static>(
@@ -273,3 +1441,169 @@ fn spawn_log_thread<R: Read + Send + 'static>(
         }
     }))
 }
+
+#[test]
+fn verify_read_only_is_consistent_across_engines() {
+    use crate::configs::index::Package as PackageIndex;
+    use crate::configs::version::Package as PackageVersion;
+    let mut package = Package {
+        name: "busybox".to_string(),
+        index: PackageIndex::new("busybox"),
+        versions: PackageVersion::new("latest"),
+    };
+    package.index.read_only = true;
+
+    let mut docker_args = Vec::new();
+    add_read_only(&package, &Engine::Docker, &mut docker_args);
+    assert_eq!(docker_args, vec!["--read-only"]);
+
+    let mut podman_args = Vec::new();
+    add_read_only(&package, &Engine::Podman, &mut podman_args);
+    assert_eq!(podman_args, vec!["--read-only", "--read-only-tmpfs=false"]);
+}
+
+#[test]
+fn verify_passwd_entries_are_appended() {
+    let image_passwd = "root:x:0:0:root:/root:/bin/sh\nnobody:x:65534:65534::/:/sbin/nologin";
+    assert_eq!(
+        with_entry(
+            image_passwd.to_string(),
+            1000,
+            "me:x:1000:1000:me:/home/hbox:/bin/sh".to_string()
+        ),
+        "root:x:0:0:root:/root:/bin/sh\nnobody:x:65534:65534::/:/sbin/nologin\nme:x:1000:1000:me:/home/hbox:/bin/sh\n"
+    );
+    assert_eq!(
+        with_entry("root:x:0:\n".to_string(), 0, "root:x:0:".to_string()),
+        "root:x:0:\n"
+    );
+}
+
+#[test]
+fn verify_passthrough_patterns() {
+    assert!(matches_pattern("AWS_*", "AWS_PROFILE"));
+    assert!(matches_pattern("AWS_*", "AWS_"));
+    assert!(matches_pattern("*_PROXY", "HTTPS_PROXY"));
+    assert!(matches_pattern("*", "HOME"));
+    assert!(matches_pattern("GIT_*_NAME", "GIT_AUTHOR_NAME"));
+    assert!(matches_pattern("HOME", "HOME"));
+    assert!(!matches_pattern("HOME", "HOMEDIR"));
+    assert!(!matches_pattern("AWS_*", "MY_AWS_PROFILE"));
+    assert!(!matches_pattern("GIT_*_NAME", "GIT_AUTHOR_EMAIL"));
+}
+
+#[test]
+fn verify_build_options_are_translated_per_engine() {
+    let build: Build = serde_json::from_value(serde_json::json!({
+        "target": "release",
+        "labels": {"org.opencontainers.image.version": "${hbox_package_version}"},
+        "cache_from": ["registry.local:5000/tools/cache:latest"],
+        "secrets": [{"id": "npmrc", "src": "/run/secrets/npmrc"}]
+    }))
+    .unwrap();
+    let mut package = Package {
+        name: "tool".to_string(),
+        index: crate::configs::index::Package::new("tool"),
+        versions: crate::configs::version::Package::new("1.0"),
+    };
+    package.index.image.build = Some(build.clone());
+    let context = Context::from(&package);
+
+    let (mut docker_args, mut docker_envs) = (Vec::new(), Vec::new());
+    add_build_options(
+        &build,
+        &Engine::Docker,
+        &context,
+        false,
+        &mut docker_args,
+        &mut docker_envs,
+    )
+    .unwrap();
+    assert_eq!(
+        docker_args,
+        vec![
+            "--target",
+            "release",
+            "--label",
+            "org.opencontainers.image.version=1.0",
+            "--cache-from",
+            "registry.local:5000/tools/cache:latest",
+            "--secret",
+            "id=npmrc,src=/run/secrets/npmrc",
+        ]
+    );
+    assert_eq!(
+        docker_envs,
+        vec![("DOCKER_BUILDKIT".to_string(), "1".to_string())]
+    );
+
+    let (mut podman_args, mut podman_envs) = (Vec::new(), Vec::new());
+    add_build_options(
+        &build,
+        &Engine::Podman,
+        &context,
+        false,
+        &mut podman_args,
+        &mut podman_envs,
+    )
+    .unwrap();
+    assert_eq!(
+        podman_args,
+        vec![
+            "--target",
+            "release",
+            "--label",
+            "org.opencontainers.image.version=1.0",
+            "--cache-from",
+            "registry.local:5000/tools/cache",
+            "--secret",
+            "id=npmrc,src=/run/secrets/npmrc",
+        ]
+    );
+    assert!(podman_envs.is_empty());
+
+    package.index.platform = Some("linux/amd64".to_string());
+    package.index.image.build.as_mut().unwrap().platform = Some("linux/arm64".to_string());
+    let mut run_args = Vec::new();
+    add_platform(&package, &mut run_args);
+    assert_eq!(run_args, vec!["--platform", "linux/arm64"]);
+}
+
+#[test]
+fn verify_missing_images_are_recovered() {
+    let mut package = Package {
+        name: "busybox".to_string(),
+        index: crate::configs::index::Package::new("busybox"),
+        versions: crate::configs::version::Package::new("latest"),
+    };
+    let mut config = Root::default();
+    assert_eq!(
+        missing_image(&package, "busybox:latest", &config),
+        MissingImage::Pull
+    );
+
+    package.index.image.build = Some(serde_json::from_str("{}").unwrap());
+    assert_eq!(
+        missing_image(&package, "busybox:latest", &config),
+        MissingImage::Build
+    );
+
+    config.images.auto_pull = false;
+    assert_eq!(
+        missing_image(&package, "busybox:latest", &config),
+        MissingImage::Fail(
+            "Image 'busybox:latest' isn't available locally. Run 'hbox add busybox@latest' to get it again."
+                .to_string()
+        )
+    );
+
+    config.offline = true;
+    config.images.auto_pull = true;
+    assert_eq!(
+        missing_image(&package, "busybox:latest", &config),
+        MissingImage::Fail(
+            "Image 'busybox:latest' isn't available locally and hbox is in offline mode."
+                .to_string()
+        )
+    );
+}
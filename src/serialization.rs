@@ -1,10 +1,18 @@
+use crate::dry_run::{is_dry_run, plan};
+use log::warn;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::ser::PrettyFormatter;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
 use std::error::Error;
 use std::fs;
 use std::fs::File;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
 pub fn parse_json<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
     let json_data = fs::read_to_string(path).map_err(|e| Box::new(e) as Box<dyn Error>)?;
@@ -13,13 +21,225 @@ pub fn parse_json<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>>
 }
 
 pub fn save_json<T: Serialize>(data: &T, path: &Path) -> Result<(), Box<dyn Error>> {
-    let file = File::create(path).map_err(|e| Box::new(e) as Box<dyn Error>)?;
-
+    if is_dry_run() {
+        plan(format!("write {}", path.display()));
+        return Ok(());
+    }
+    let mut contents = Vec::new();
     let formatter = PrettyFormatter::with_indent(b"  ");
-    let mut serializer = serde_json::Serializer::with_formatter(file, formatter);
+    let mut serializer = serde_json::Serializer::with_formatter(&mut contents, formatter);
 
     data.serialize(&mut serializer)
         .map_err(|e| Box::new(e) as Box<dyn Error>)?;
 
-    Ok(())
+    write_atomically(path, &contents)
+}
+
+/// Writes a file through a temporary file renamed over it, so the file is never left empty or
+/// half-written, even if hbox is interrupted.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Invalid file path {:?}", path))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map_err(|e| Box::new(e) as Box<dyn Error>)
+}
+
+/// Replaces the characters of a value that aren't safe in file names, like `/` and `:` in
+/// image references.
+pub fn file_name_part(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns the SHA-256 digest of a file, as a lowercase hex string.
+pub fn file_checksum(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Temporary directory, like the one holding the files of a bundle or a generated build
+/// context. It is removed when dropped.
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn create(purpose: &str) -> Result<Self, Box<dyn Error>> {
+        let id: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+        let path = env::temp_dir().join(format!("hbox-{}-{}", purpose, id));
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!(
+                "Could not remove temporary directory {:?}: {}",
+                self.path, e
+            );
+        }
+    }
+}
+
+pub fn to_yaml<T: Serialize>(data: &T) -> Result<String, Box<dyn Error>> {
+    let value = serde_json::to_value(data).map_err(|e| Box::new(e) as Box<dyn Error>)?;
+    let mut yaml = yaml_lines(&value).join("\n");
+    yaml.push('\n');
+    Ok(yaml)
+}
+
+fn yaml_lines(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .flat_map(|(key, value)| {
+                let key = yaml_scalar(&Value::String(key.clone()));
+                if is_yaml_inline(value) {
+                    vec![format!("{}: {}", key, yaml_scalar(value))]
+                } else {
+                    let mut lines = vec![format!("{}:", key)];
+                    lines.extend(yaml_lines(value).into_iter().map(|l| format!("  {}", l)));
+                    lines
+                }
+            })
+            .collect(),
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .flat_map(|item| {
+                if is_yaml_inline(item) {
+                    vec![format!("- {}", yaml_scalar(item))]
+                } else {
+                    yaml_lines(item)
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, l)| {
+                            if i == 0 {
+                                format!("- {}", l)
+                            } else {
+                                format!("  {}", l)
+                            }
+                        })
+                        .collect()
+                }
+            })
+            .collect(),
+        _ => vec![yaml_scalar(value)],
+    }
+}
+
+fn is_yaml_inline(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => true,
+    }
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        Value::String(text) if is_yaml_identifier(text) => text.clone(),
+        // JSON strings are valid YAML double-quoted scalars.
+        Value::String(_) => value.to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Indicates if a string can be written without quotes. Only strict identifiers are, so no
+/// YAML parser reads them as numbers, booleans or null, whatever YAML version it follows.
+fn is_yaml_identifier(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+        && !matches!(
+            text.to_lowercase().as_str(),
+            "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+        )
+}
+
+#[test]
+fn verify_yaml_output() {
+    let value = serde_json::json!({
+        "name": "node",
+        "current": "18",
+        "versions": ["18", "latest"],
+        "binaries": [],
+        "image": {"name": "docker.io/node", "build": null},
+        "shims": [{"name": "node", "enabled": true}],
+        "label": "key: value",
+        "tricky": ["0x10", ".inf", "1_000", "12:30", "1e3", "no", "~"],
+    });
+    assert_eq!(
+        to_yaml(&value).unwrap(),
+        r#"binaries: []
+current: "18"
+image:
+  build: null
+  name: docker.io/node
+label: "key: value"
+name: node
+shims:
+  - enabled: true
+    name: node
+tricky:
+  - "0x10"
+  - ".inf"
+  - "1_000"
+  - "12:30"
+  - "1e3"
+  - "no"
+  - "~"
+versions:
+  - "18"
+  - latest
+"#
+    );
+}
+
+#[test]
+fn verify_atomic_writes() {
+    let dir = std::env::temp_dir().join(format!("hbox-atomic-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("versions.json");
+
+    fs::write(&path, "old").unwrap();
+    save_json(&serde_json::json!({"current": "18"}), &path).unwrap();
+
+    let saved: Value = parse_json(&path).unwrap();
+    assert_eq!(saved["current"], "18");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
 }
@@ -1,60 +1,482 @@
 use crate::configs::app::AppConfig;
-use log::debug;
+use crate::configs::shim::{Shim, ShimConfig};
+use crate::configs::user::{ConflictPolicy, ShimMode, UserConfig};
+use crate::dry_run::{command_line, is_dry_run, plan};
+use crate::packages::Package;
+use crate::serialization::write_atomically;
+use log::{debug, warn};
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
-pub fn add_shim(name: &str, binary: Option<&str>) -> std::io::Result<()> {
+/// Creates the shim named `shim` of a package, following the configured conflict policy.
+/// Returns the name of the shim file if it was created.
+pub fn add_shim(package: &Package, shim: &str) -> Result<Option<String>, Box<dyn Error>> {
     let config = AppConfig::load();
-    let shim_name = binary.unwrap_or_else(|| name);
-    let shims_file_path = get_shims_path(shim_name, config);
+    let shims_config = UserConfig::load().unwrap_or_default().shims;
+    let command = package.shim_command(shim);
 
-    if !shims_file_path.exists() {
-        fs::create_dir_all(shims_file_path.parent().unwrap())?;
-        debug!("Creating shim {:?}", &shims_file_path);
-        let mut shim_file = File::create(&shims_file_path)?;
+    let shim_name = match find_conflict(package, shim, &config)? {
+        None => shim.to_string(),
+        Some(conflict) => match shims_config.conflicts {
+            ConflictPolicy::Fail => {
+                return Err(format!(
+                    "Shim '{}' {}. Remove the conflicting command or change 'shims.conflicts'.",
+                    shim, conflict
+                )
+                .into());
+            }
+            // Shims of other packages are never replaced, only commands in PATH are shadowed.
+            ConflictPolicy::Warn if matches!(conflict, Conflict::Shim(_)) => {
+                warn!("Skipping shim '{}', it {}", shim, conflict);
+                return Ok(None);
+            }
+            ConflictPolicy::Warn => {
+                warn!("Shim '{}' {}", shim, conflict);
+                shim.to_string()
+            }
+            ConflictPolicy::Skip => {
+                warn!("Skipping shim '{}', it {}", shim, conflict);
+                return Ok(None);
+            }
+            ConflictPolicy::Alias => {
+                let alias = package.shim_alias(shim);
+                if let Some(alias_conflict) = find_conflict(package, &alias, &config)? {
+                    return Err(format!(
+                        "Shim '{}' {}, and its alias '{}' {}.",
+                        shim, conflict, alias, alias_conflict
+                    )
+                    .into());
+                }
+                warn!("Creating shim '{}' as '{}', it {}", shim, alias, conflict);
+                alias
+            }
+        },
+    };
 
-        let command = match binary {
-            Some(bin) => format!("{}::{}", name, bin),
-            None => name.to_string(),
-        };
+    if let Some(owner) = shim_owner(&shim_name)? {
+        if owner != command {
+            debug!("Keeping shim '{}', it runs '{}'", shim_name, owner);
+            return Ok(None);
+        }
+    }
 
-        if std::env::consts::OS == "windows" {
-            shim_file.write_all(b"@echo off\n")?;
-            shim_file.write_all(format!("hbox.exe run {} %*\n", command).as_bytes())?;
-        } else {
-            shim_file.write_all(b"#!/bin/sh\n")?;
-            shim_file.write_all(format!("hbox run {} \"$@\"\n", command).as_bytes())?;
+    let shims_file_path = get_shims_path(&shim_name, &shims_config.mode, &config);
+    if is_dry_run() {
+        if shims_file_path.symlink_metadata().is_err() {
+            plan(format!(
+                "create shim {} for '{}'",
+                shims_file_path.display(),
+                command
+            ));
+        }
+        return Ok(None);
+    }
+    let created = shims_file_path.symlink_metadata().is_err();
+    if created {
+        fs::create_dir_all(shims_file_path.parent().unwrap())?;
+        debug!(
+            "Creating {:?} shim {:?}",
+            shims_config.mode, &shims_file_path
+        );
+
+        match shims_config.mode {
+            ShimMode::Script => create_script(&shims_file_path, &command)?,
+            ShimMode::Symlink => create_symlink(&shims_file_path)?,
+            ShimMode::Hardlink => fs::hard_link(env::current_exe()?, &shims_file_path)?,
         }
+    }
+    let binary = (shim != package.name).then(|| shim.to_string());
+    ShimConfig::upsert(
+        &shim_name,
+        Shim {
+            package: package.name.clone(),
+            binary,
+        },
+    )?;
+    Ok(created.then_some(shim_name))
+}
+
+/// Removes the shim named `shim` of a package, or its alias, as long as they run this package.
+/// Returns whether any shim was removed.
+pub fn remove_shim(package: &Package, shim: &str) -> Result<bool, Box<dyn Error>> {
+    let command = package.shim_command(shim);
+    let mut removed = false;
 
-        if std::env::consts::OS != "windows" {
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                let mut perms = shim_file.metadata()?.permissions();
-                perms.set_mode(0o755); // rwx for user, rx for group and others
-                fs::set_permissions(&shims_file_path, perms)?;
+    for name in [shim.to_string(), package.shim_alias(shim)] {
+        match shim_owner(&name)? {
+            Some(owner) if owner == command => {
+                delete_shim(&name)?;
+                removed = true;
             }
+            Some(_) => debug!("Keeping shim '{}', it runs another package", name),
+            None => {}
+        }
+    }
+    Ok(removed)
+}
+
+/// Recreates the shims of all installed packages, using the configured shim mode.
+pub fn reshim() -> Result<(), Box<dyn Error>> {
+    let packages = Package::load_all()?;
+    for (name, shim) in ShimConfig::load()? {
+        if !packages.iter().any(|package| package.name == shim.package) {
+            debug!(
+                "Removing shim '{}' of '{}', it's not installed",
+                name, shim.package
+            );
+            delete_shim(&name)?;
+        }
+    }
+    for package in &packages {
+        for shim in package.shim_names() {
+            remove_shim(package, &shim)?;
+        }
+    }
+    for package in &packages {
+        for shim in package.shim_names() {
+            add_shim(package, &shim)?;
         }
     }
     Ok(())
 }
 
-pub fn remove_shim(name: &str) -> std::io::Result<()> {
+/// Finds the package and binary a shim named `shim_name` runs. It's used by `hbox which` and
+/// when hbox is invoked through a link shim, where the shim name is the program name.
+pub fn find_shim(shim_name: &str) -> Option<Shim> {
+    match ShimConfig::find(shim_name) {
+        Ok(Some(shim)) => return Some(shim),
+        Ok(None) => {}
+        Err(e) => warn!("Could not load shims manifest: {}", e),
+    }
+    // Shims created before the manifest existed are resolved from the installed packages.
+    let packages = match Package::load_all() {
+        Ok(packages) => packages,
+        Err(e) => {
+            warn!("Could not load packages: {}", e);
+            return None;
+        }
+    };
+    packages.iter().find_map(|package| {
+        package
+            .shim_names()
+            .into_iter()
+            .find(|shim| shim == shim_name || package.shim_alias(shim) == shim_name)
+            .map(|shim| Shim {
+                package: package.name.clone(),
+                binary: (shim != package.name).then_some(shim),
+            })
+    })
+}
+
+/// Path of the existing shim file named `name`, if any.
+pub fn find_shim_path(name: &str) -> Option<PathBuf> {
+    let config = AppConfig::load();
+    [ShimMode::Script, ShimMode::Symlink]
+        .iter()
+        .map(|mode| get_shims_path(name, mode, &config))
+        .find(|path| path.symlink_metadata().is_ok())
+}
+
+enum Conflict {
+    /// An executable found in `PATH`, and whether it comes before the shims directory.
+    Command(PathBuf, bool),
+    /// A shim created for another package's command.
+    Shim(String),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Command(path, true) => {
+                write!(f, "is shadowed by {:?}, which comes first in PATH", path)
+            }
+            Conflict::Command(path, false) => write!(f, "shadows {:?}", path),
+            Conflict::Shim(command) => write!(f, "is already used by '{}'", command),
+        }
+    }
+}
+
+fn find_conflict(
+    package: &Package,
+    shim_name: &str,
+    config: &AppConfig,
+) -> Result<Option<Conflict>, Box<dyn Error>> {
+    if let Some(owner) = shim_owner(shim_name)? {
+        return Ok(if owner.split("::").next() == Some(package.name.as_str()) {
+            None
+        } else {
+            Some(Conflict::Shim(owner))
+        });
+    }
+
+    let Some(path) = env::var_os("PATH") else {
+        return Ok(None);
+    };
+    let shims_path = config.shims_path();
+    let shims_path = shims_path.canonicalize().unwrap_or(shims_path);
+    let dirs = env::split_paths(&path)
+        .filter(|dir| dir.is_dir())
+        .map(|dir| dir.canonicalize().unwrap_or(dir))
+        .collect::<Vec<_>>();
+    let shims_position = dirs.iter().position(|dir| *dir == shims_path);
+    Ok(dirs
+        .iter()
+        .enumerate()
+        .filter(|(_, dir)| **dir != shims_path)
+        .find_map(|(position, dir)| {
+            let path = find_executable(dir, shim_name)?;
+            let before_shims = shims_position.is_some_and(|shims| position < shims);
+            Some(Conflict::Command(path, before_shims))
+        }))
+}
+
+/// Returns the command run by the existing shim named `name`, as recorded in the shims
+/// manifest or, for shims created before the manifest existed, as read from the shim itself.
+fn shim_owner(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(shims_file_path) = find_shim_path(name) else {
+        return Ok(None);
+    };
+    match ShimConfig::find(name)? {
+        Some(shim) => Ok(Some(shim.command())),
+        None => Ok(Some(
+            read_shim_command(&shims_file_path).unwrap_or_else(|| format!("{:?}", shims_file_path)),
+        )),
+    }
+}
+
+/// Removes the shim file named `name` and its manifest entry, regardless of its owner.
+pub fn delete_shim(name: &str) -> Result<(), Box<dyn Error>> {
     let config = AppConfig::load();
-    let shims_file_path = get_shims_path(name, config);
+    for mode in [ShimMode::Script, ShimMode::Symlink] {
+        let shims_file_path = get_shims_path(name, &mode, &config);
+        if shims_file_path.symlink_metadata().is_ok() {
+            debug!("Removing shim {:?}", &shims_file_path);
+            if is_dry_run() {
+                plan(format!("remove shim {}", shims_file_path.display()));
+                return Ok(());
+            }
+            fs::remove_file(shims_file_path)?;
+        }
+    }
+    if is_dry_run() {
+        return Ok(());
+    }
+    ShimConfig::remove(name)
+}
+
+fn find_executable(dir: &Path, name: &str) -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        return ["exe", "bat", "cmd"]
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file());
+    }
+    let path = dir.join(name);
+    let metadata = path.metadata().ok()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return None;
+        }
+    }
+    metadata.is_file().then_some(path)
+}
+
+/// Reads the command an existing shim runs. Script shims embed it, while link shims are
+/// resolved from their name.
+fn read_shim_command(shims_file_path: &Path) -> Option<String> {
+    let mut header = [0u8; 2];
+    let is_script = File::open(shims_file_path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map(|_| &header == b"#!" || &header == b"@e")
+        .unwrap_or(false);
+
+    if is_script && !shims_file_path.is_symlink() {
+        let content = fs::read_to_string(shims_file_path).ok()?;
+        let mut words = content.split_whitespace();
+        words.find(|word| *word == "run")?;
+        words.next().map(|command| command.to_string())
+    } else {
+        find_shim(shim_name(shims_file_path)?).map(|shim| shim.command())
+    }
+}
+
+/// Returns the name of the shim a link shim or the hbox binary is invoked as. Only the `.exe`
+/// extension is removed on Windows, so shims like `python3.12` keep their full name.
+pub fn shim_name(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    if cfg!(target_os = "windows") {
+        Some(name.strip_suffix(".exe").unwrap_or(name))
+    } else {
+        Some(name)
+    }
+}
+
+fn create_script(shims_file_path: &Path, command: &str) -> Result<(), Box<dyn Error>> {
+    // Prefer the hbox found in PATH, falling back to the binary that created the shim.
+    let hbox_path = env::current_exe()?;
 
-    if shims_file_path.exists() {
-        debug!("Removing shim {:?}", &shims_file_path);
-        fs::remove_file(shims_file_path)?;
+    let script = if std::env::consts::OS == "windows" {
+        format!(
+            "@echo off\nwhere hbox.exe >nul 2>nul && (hbox.exe run {command} %*) || (\"{}\" run {command} %*)\n",
+            hbox_path.display(),
+        )
+    } else {
+        let run_args = ["run".to_string(), command.to_string()];
+        format!(
+            "#!/bin/sh\ncommand -v hbox >/dev/null 2>&1 && exec {} \"$@\"\nexec {} \"$@\"\n",
+            command_line("hbox", &run_args),
+            command_line(&hbox_path.to_string_lossy(), &run_args),
+        )
+    };
+    write_atomically(shims_file_path, script.as_bytes())?;
+
+    if std::env::consts::OS != "windows" {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(shims_file_path)?.permissions();
+            perms.set_mode(0o755); // rwx for user, rx for group and others
+            fs::set_permissions(shims_file_path, perms)?;
+        }
     }
     Ok(())
 }
 
-fn get_shims_path(name: &str, config: AppConfig) -> PathBuf {
+fn create_symlink(shims_file_path: &Path) -> std::io::Result<()> {
+    let hbox_path = env::current_exe()?;
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(hbox_path, shims_file_path)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::fs::symlink_file(hbox_path, shims_file_path)
+    }
+}
+
+fn get_shims_path(name: &str, mode: &ShimMode, config: &AppConfig) -> PathBuf {
     if cfg!(target_os = "windows") {
-        config.shims_path().join(format!("{}.bat", name))
+        match mode {
+            ShimMode::Script => config.shims_path().join(format!("{}.bat", name)),
+            ShimMode::Symlink | ShimMode::Hardlink => {
+                config.shims_path().join(format!("{}.exe", name))
+            }
+        }
     } else {
         config.shims_path().join(name)
     }
 }
+
+#[test]
+fn verify_shim_name() {
+    assert_eq!(
+        shim_name(Path::new("/hbox/shims/python3.12")),
+        Some("python3.12")
+    );
+    assert_eq!(shim_name(Path::new("jq")), Some("jq"));
+    let expected = if cfg!(target_os = "windows") {
+        "node"
+    } else {
+        "node.exe"
+    };
+    assert_eq!(shim_name(Path::new("node.exe")), Some(expected));
+}
+
+/// Returns a package providing the `hbox-test-tool` binary, a name no host command uses.
+#[cfg(test)]
+fn test_package(name: &str) -> Package {
+    let mut index = crate::configs::index::Package::new(name);
+    index.binaries = Some(vec![serde_json::from_str(
+        r#"{"name": "hbox-test-tool", "path": "/bin/tool"}"#,
+    )
+    .unwrap()]);
+    Package {
+        name: name.to_string(),
+        index,
+        versions: crate::configs::version::Package::new("latest"),
+    }
+}
+
+#[test]
+fn verify_conflict_policy() {
+    use crate::configs::user::{Root, Shims};
+
+    crate::configs::app::with_temp_base_dir(|| {
+        let set_policy = |conflicts| {
+            UserConfig::save(Root {
+                shims: Shims {
+                    conflicts,
+                    ..Shims::default()
+                },
+                ..Root::default()
+            })
+            .unwrap()
+        };
+        let owner = || ShimConfig::find("hbox-test-tool").unwrap().unwrap().package;
+        let (first, second) = (test_package("first"), test_package("second"));
+        assert_eq!(
+            add_shim(&first, "hbox-test-tool").unwrap().as_deref(),
+            Some("hbox-test-tool")
+        );
+
+        for policy in [ConflictPolicy::Warn, ConflictPolicy::Skip] {
+            set_policy(policy);
+            assert_eq!(add_shim(&second, "hbox-test-tool").unwrap(), None);
+            assert_eq!(owner(), "first");
+        }
+
+        set_policy(ConflictPolicy::Fail);
+        assert!(add_shim(&second, "hbox-test-tool").is_err());
+        assert_eq!(owner(), "first");
+
+        set_policy(ConflictPolicy::Alias);
+        assert_eq!(
+            add_shim(&second, "hbox-test-tool").unwrap().as_deref(),
+            Some("second-hbox-test-tool")
+        );
+        assert_eq!(owner(), "first");
+        assert_eq!(
+            ShimConfig::find("second-hbox-test-tool")
+                .unwrap()
+                .unwrap()
+                .package,
+            "second"
+        );
+    });
+}
+
+#[test]
+fn verify_shims_of_other_packages_are_kept() {
+    use crate::configs::user::{Root, Shims};
+
+    crate::configs::app::with_temp_base_dir(|| {
+        UserConfig::save(Root {
+            shims: Shims {
+                conflicts: ConflictPolicy::Alias,
+                ..Shims::default()
+            },
+            ..Root::default()
+        })
+        .unwrap();
+        let (first, second) = (test_package("first"), test_package("second"));
+        add_shim(&second, "hbox-test-tool").unwrap();
+        add_shim(&first, "hbox-test-tool").unwrap();
+
+        // Only the alias of the first package is removed, the shim with the same name runs the
+        // second package.
+        assert!(remove_shim(&first, "hbox-test-tool").unwrap());
+        assert!(find_shim_path("first-hbox-test-tool").is_none());
+        assert!(find_shim_path("hbox-test-tool").is_some());
+        assert_eq!(
+            ShimConfig::find("hbox-test-tool").unwrap().unwrap().package,
+            "second"
+        );
+        assert!(!remove_shim(&first, "hbox-test-tool").unwrap());
+    });
+}
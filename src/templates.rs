@@ -0,0 +1,108 @@
+use crate::configs::index::{Template, TemplateName};
+
+impl TemplateName {
+    fn base_image(&self) -> &'static str {
+        match self {
+            TemplateName::Alpine => "alpine:latest",
+            TemplateName::Debian => "debian:stable-slim",
+            TemplateName::Python => "python:3-slim",
+            TemplateName::Node => "node:lts-slim",
+        }
+    }
+
+    fn install_command(&self, packages: &str) -> String {
+        match self {
+            TemplateName::Alpine => format!("apk add --no-cache {}", packages),
+            TemplateName::Debian => format!(
+                "apt-get update && apt-get install -y --no-install-recommends {} && rm -rf /var/lib/apt/lists/*",
+                packages
+            ),
+            TemplateName::Python => format!("pip install --no-cache-dir {}", packages),
+            TemplateName::Node => format!("npm install -g {}", packages),
+        }
+    }
+
+    /// Indicates if a package spec already selects a version, like `httpie==3.2.2`.
+    fn has_version(&self, spec: &str) -> bool {
+        spec.contains("${")
+            || match self {
+                TemplateName::Alpine => spec.contains(['=', '<', '>', '~']),
+                TemplateName::Debian => spec.contains('='),
+                TemplateName::Python => spec.contains(['=', '<', '>', '~', '!', '@']),
+                TemplateName::Node => spec.get(1..).is_some_and(|rest| rest.contains('@')),
+            }
+    }
+
+    /// Selects a version of a package, matching versions starting with it when the package
+    /// manager requires full versions, like `1.7.1-r0` with `apk`.
+    fn pin(&self, spec: &str, version: &str) -> String {
+        match self {
+            TemplateName::Alpine => format!("{}~{}", spec, version),
+            TemplateName::Debian => format!("{}={}*", spec, version),
+            TemplateName::Python => format!("{}=={}", spec, version),
+            TemplateName::Node => format!("{}@{}", spec, version),
+        }
+    }
+}
+
+/// Returns the Dockerfile of a template build for a version of a package. The first package
+/// installed is the tool itself, so it's pinned to that version, unless the version is
+/// `latest` or the package already selects one. `IMAGE_NAME` and `IMAGE_VERSION` are declared
+/// before `FROM` as well, so they can be used in the base image too.
+pub fn render(template: &Template, version: &str) -> String {
+    let base = template
+        .base
+        .as_deref()
+        .unwrap_or(template.name.base_image());
+    let mut lines = vec![
+        "ARG IMAGE_NAME".to_string(),
+        "ARG IMAGE_VERSION".to_string(),
+        format!("FROM {}", base),
+        "ARG IMAGE_NAME".to_string(),
+        "ARG IMAGE_VERSION".to_string(),
+    ];
+    if let Some((tool, others)) = template.packages.split_first() {
+        let tool = if version == "latest" || template.name.has_version(tool) {
+            tool.clone()
+        } else {
+            template.name.pin(tool, version)
+        };
+        let packages = [tool].iter().chain(others).cloned().collect::<Vec<_>>();
+        lines.push(format!(
+            "RUN {}",
+            template.name.install_command(&packages.join(" "))
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[test]
+fn verify_template_rendering() {
+    let template = Template {
+        name: TemplateName::Python,
+        base: None,
+        packages: vec!["httpie".to_string(), "rich".to_string()],
+    };
+    assert_eq!(
+        render(&template, "3.2.2"),
+        "ARG IMAGE_NAME\nARG IMAGE_VERSION\nFROM python:3-slim\nARG IMAGE_NAME\nARG IMAGE_VERSION\nRUN pip install --no-cache-dir httpie==3.2.2 rich\n"
+    );
+    assert!(render(&template, "latest").ends_with("pip install --no-cache-dir httpie rich\n"));
+
+    let template = Template {
+        name: TemplateName::Node,
+        base: Some("node:${IMAGE_VERSION}-alpine".to_string()),
+        packages: vec!["@angular/cli@17".to_string()],
+    };
+    assert_eq!(
+        render(&template, "20"),
+        "ARG IMAGE_NAME\nARG IMAGE_VERSION\nFROM node:${IMAGE_VERSION}-alpine\nARG IMAGE_NAME\nARG IMAGE_VERSION\nRUN npm install -g @angular/cli@17\n"
+    );
+
+    let template = Template {
+        name: TemplateName::Debian,
+        base: None,
+        packages: vec!["jq".to_string()],
+    };
+    assert!(render(&template, "1.6").contains("--no-install-recommends jq=1.6* &&"));
+}
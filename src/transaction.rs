@@ -0,0 +1,106 @@
+use crate::configs::version::{Package as PackageVersion, VersionConfig};
+use crate::packages::Package;
+use crate::runner::remove_image;
+use crate::shims::{add_shim, delete_shim};
+use log::{info, warn};
+use std::error::Error;
+
+/// Change applied while adding or removing a package.
+pub enum Change {
+    /// A shim file was created.
+    ShimCreated(String),
+    /// A shim of the package was removed.
+    ShimRemoved(Box<Package>, String),
+    /// The versions file of a package was written or removed, holding its previous content.
+    VersionsChanged(String, Option<PackageVersion>),
+    /// An image was pulled or built, and wasn't available before.
+    ImageAdded(String),
+}
+
+/// Changes applied by a command, undone in reverse order if a later step fails, so a failure
+/// doesn't leave shims without a versions entry or the other way around.
+#[derive(Default)]
+pub struct Transaction {
+    changes: Vec<Change>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    /// Runs `apply`, rolling back the changes it recorded if it fails.
+    pub fn run<F>(apply: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce(&mut Transaction) -> Result<(), Box<dyn Error>>,
+    {
+        let mut transaction = Self::new();
+        apply(&mut transaction).inspect_err(|_| transaction.rollback())
+    }
+
+    /// Undoes the recorded changes. Failures are logged, so every change gets a chance to be
+    /// undone.
+    pub fn rollback(&mut self) {
+        if self.changes.is_empty() {
+            return;
+        }
+        info!("Rolling back {} change(s)", self.changes.len());
+        while let Some(change) = self.changes.pop() {
+            if let Err(e) = undo(&change) {
+                warn!("Could not roll back a change: {}", e);
+            }
+        }
+    }
+}
+
+fn undo(change: &Change) -> Result<(), Box<dyn Error>> {
+    match change {
+        Change::ShimCreated(name) => delete_shim(name),
+        Change::ShimRemoved(package, shim) => add_shim(package, shim).map(|_| ()),
+        Change::VersionsChanged(name, None) => VersionConfig::remove(name),
+        Change::VersionsChanged(name, Some(previous)) => VersionConfig::save(name, previous),
+        Change::ImageAdded(image) => {
+            if remove_image(image) {
+                Ok(())
+            } else {
+                Err(format!("Failed to remove image '{}'", image).into())
+            }
+        }
+    }
+}
+
+#[test]
+fn verify_rollback_restores_shims_and_versions() {
+    use crate::shims::{find_shim_path, remove_shim};
+
+    crate::configs::app::with_temp_base_dir(|| {
+        let package = Package {
+            name: "hbox-test-tool".to_string(),
+            index: crate::configs::index::Package::new("hbox-test-tool"),
+            versions: PackageVersion::new("1.0"),
+        };
+        add_shim(&package, "hbox-test-tool").unwrap();
+        VersionConfig::save(&package.name, &package.versions).unwrap();
+
+        let result = Transaction::run(|transaction| {
+            remove_shim(&package, "hbox-test-tool")?;
+            transaction.record(Change::ShimRemoved(
+                Box::new(package.clone()),
+                "hbox-test-tool".to_string(),
+            ));
+            let previous = VersionConfig::load(package.name.clone())?;
+            VersionConfig::save(&package.name, &PackageVersion::new("2.0"))?;
+            transaction.record(Change::VersionsChanged(package.name.clone(), previous));
+            Err("pull failed".into())
+        });
+
+        assert!(result.is_err());
+        assert!(find_shim_path("hbox-test-tool").is_some());
+        let versions = VersionConfig::load(package.name.clone()).unwrap().unwrap();
+        assert_eq!(versions.current, "1.0");
+    });
+}